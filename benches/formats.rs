@@ -0,0 +1,115 @@
+//! # benches/formats.rs -- parse and serialize benchmarks for each LLSD format.
+//!
+//!  Run with "cargo bench". Cases are a large typical document,
+//!  a deeply nested tree, a map with many keys, and many small strings.
+//!  There is also a few-MB binary asset, parsed from a slice and through a dyn Read.
+//
+//  Animats
+//  October, 2026.
+//  License: LGPL.
+//
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use serde_llsd::{LLSDMap, LLSDValue};
+use uuid::Uuid;
+
+/// Large document, like a region object list. Array of small maps of mixed types.
+fn large_document() -> LLSDValue {
+    object_list(2000)
+}
+
+/// Array of count small maps of mixed types.
+fn object_list(count: i32) -> LLSDValue {
+    LLSDValue::Array(
+        (0..count)
+            .map(|n| {
+                let fields: LLSDMap = [
+                    ("local_id".to_string(), LLSDValue::Integer(n)),
+                    ("object_id".to_string(), LLSDValue::UUID(Uuid::from_u128(n as u128))),
+                    ("name".to_string(), LLSDValue::String(format!("Object {}", n))),
+                    ("scale".to_string(), LLSDValue::Real(n as f64 * 0.25)),
+                    ("physical".to_string(), LLSDValue::Boolean(n % 2 == 0)),
+                    ("created".to_string(), LLSDValue::Date(1138804193.0 + n as f64)),
+                    ("texture".to_string(), LLSDValue::Binary(vec![n as u8; 32])),
+                    (
+                        "position".to_string(),
+                        LLSDValue::Array(vec![LLSDValue::Real(128.0), LLSDValue::Real(64.5), LLSDValue::Real(22.0)]),
+                    ),
+                ]
+                .into_iter()
+                .collect();
+                LLSDValue::Map(fields)
+            })
+            .collect(),
+    )
+}
+
+/// Deeply nested tree of single-element arrays and maps.
+fn deep_tree() -> LLSDValue {
+    let mut val = LLSDValue::Integer(0);
+    for n in 0..200 {
+        val = if n % 2 == 0 {
+            LLSDValue::Array(vec![val])
+        } else {
+            LLSDValue::Map([("child".to_string(), val)].into_iter().collect())
+        };
+    }
+    val
+}
+
+/// One map with many keys.
+fn key_heavy_map() -> LLSDValue {
+    LLSDValue::Map((0..10000).map(|n| (format!("key_{}", n), LLSDValue::Integer(n))).collect())
+}
+
+/// Array of many small strings. Exercises per-leaf allocation in the parsers.
+fn small_strings() -> LLSDValue {
+    LLSDValue::Array((0..10000).map(|n| LLSDValue::String(format!("s{}", n))).collect())
+}
+
+/// Benchmark serialization and parsing of one value in all three formats.
+fn bench_value(c: &mut Criterion, name: &str, val: &LLSDValue) {
+    let xml = serde_llsd::to_string(val, false).unwrap();
+    let binary = serde_llsd::to_bytes(val).unwrap();
+    let notation = serde_llsd::notation_to_string(val).unwrap();
+    c.bench_function(&format!("{} xml serialize", name), |b| {
+        b.iter(|| serde_llsd::to_string(black_box(val), false).unwrap())
+    });
+    c.bench_function(&format!("{} xml parse", name), |b| {
+        b.iter(|| serde_llsd::from_str(black_box(&xml)).unwrap())
+    });
+    c.bench_function(&format!("{} binary serialize", name), |b| {
+        b.iter(|| serde_llsd::to_bytes(black_box(val)).unwrap())
+    });
+    c.bench_function(&format!("{} binary parse", name), |b| {
+        b.iter(|| serde_llsd::auto_from_bytes(black_box(&binary)).unwrap())
+    });
+    c.bench_function(&format!("{} notation serialize", name), |b| {
+        b.iter(|| serde_llsd::notation_to_string(black_box(val)).unwrap())
+    });
+    c.bench_function(&format!("{} notation parse", name), |b| {
+        b.iter(|| serde_llsd::auto_from_bytes(black_box(notation.as_bytes())).unwrap())
+    });
+}
+
+/// Binary parse of a few-MB asset. The slice form is monomorphized for the slice reader.
+/// The reader form goes through a dyn Read, with a virtual call per read.
+fn binary_reader(c: &mut Criterion) {
+    let binary = serde_llsd::to_bytes(&object_list(20000)).unwrap();
+    let body = &binary[serde_llsd::ser::binary::LLSDBINARYPREFIX.len()..];
+    c.bench_function(&format!("{} KB binary parse from slice", body.len() / 1024), |b| {
+        b.iter(|| serde_llsd::binary_from_bytes(black_box(body)).unwrap())
+    });
+    c.bench_function(&format!("{} KB binary parse from dyn Read", body.len() / 1024), |b| {
+        b.iter(|| serde_llsd::binary_from_reader(&mut std::io::Cursor::new(black_box(body))).unwrap())
+    });
+}
+
+fn formats(c: &mut Criterion) {
+    bench_value(c, "large document", &large_document());
+    bench_value(c, "deep tree", &deep_tree());
+    bench_value(c, "key-heavy map", &key_heavy_map());
+    bench_value(c, "small strings", &small_strings());
+}
+
+criterion_group!(benches, formats, binary_reader);
+criterion_main!(benches);
@@ -11,61 +11,220 @@
 //  March, 2021.
 //  License: LGPL.
 //
-use crate::LLSDValue;
+use crate::{LLSDMap, LLSDValue};
 use anyhow::{anyhow, Error};
-use std::collections::HashMap;
-use std::io::{Cursor, Read};
+use std::io::{BufReader, Cursor, Read};
 use uuid;
 //
 //  Constants
 //
 pub const LLSDBINARYPREFIX: &[u8] = b"<? LLSD/Binary ?>\n"; // binary LLSD prefix
 pub const LLSDBINARYSENTINEL: &[u8] = LLSDBINARYPREFIX; // prefix must match exactly
+/// Buffer size for from_reader_buffered.
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+/// Default limit on the declared size of one string or binary item.
+pub const DEFAULT_MAX_ALLOC: usize = 64 * 1024 * 1024;
+
+/// Character encoding of strings in binary LLSD.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StringEncoding {
+    /// UTF-8, per the spec.
+    #[default]
+    Utf8,
+    /// Latin-1, each byte is one Unicode code point. Found in pre-UTF-8 SL assets.
+    Latin1,
+}
+
+/// Options for parsing binary LLSD.
+#[derive(Debug, Clone)]
+pub struct BinaryOptions {
+    /// Encoding of strings, URIs, and map keys.
+    pub string_encoding: StringEncoding,
+    /// NON-CONFORMING. Data recovery only.
+    /// Some buggy producers wrote an integer as ASCII text after the 'i'
+    /// type code instead of 4 big-endian bytes. If set, and the 4 bytes
+    /// after 'i' are all ASCII digits, sign, or padding, parse them as text.
+    /// This misreads genuine integers whose bytes happen to be ASCII digits,
+    /// so use only on data known to be damaged this way.
+    pub lenient_ascii_numbers: bool,
+    /// Maximum nesting of maps and arrays.
+    pub max_depth: usize,
+    /// Maximum declared length of one string, URI, key, or binary item, in bytes.
+    /// A corrupt length is rejected before any memory is allocated for it.
+    pub max_alloc: usize,
+}
+
+impl Default for BinaryOptions {
+    fn default() -> Self {
+        BinaryOptions {
+            string_encoding: StringEncoding::default(),
+            lenient_ascii_numbers: false,
+            max_depth: crate::de::DEFAULT_MAX_DEPTH,
+            max_alloc: DEFAULT_MAX_ALLOC,
+        }
+    }
+}
 
 ///    Parse LLSD array expressed in binary into an LLSDObject tree. No header.
+///    Reads straight from the slice. This is the fastest way to parse binary LLSD.
 pub fn from_bytes(b: &[u8]) -> Result<LLSDValue, Error> {
+    from_bytes_with_options(b, &BinaryOptions::default())
+}
+
+///    Parse LLSD array expressed in binary into an LLSDObject tree, with options. No header.
+pub fn from_bytes_with_options(b: &[u8], options: &BinaryOptions) -> Result<LLSDValue, Error> {
+    let mut cursor: Cursor<&[u8]> = Cursor::new(b);
+    parse_value(&mut cursor, options)
+}
+
+///    Parse LLSD expressed in binary, rejecting any trailing bytes. No header.
+pub fn from_bytes_exact(b: &[u8]) -> Result<LLSDValue, Error> {
+    from_bytes_exact_with_footer(b, &[])
+}
+
+///    Parse LLSD expressed in binary, where the value may be followed by a known footer. No header.
+///    Some framing appends a fixed byte sequence after the body. That footer,
+///    or nothing, is accepted after the value. Anything else is an error.
+pub fn from_bytes_exact_with_footer(b: &[u8], footer: &[u8]) -> Result<LLSDValue, Error> {
     let mut cursor: Cursor<&[u8]> = Cursor::new(b);
-    parse_value(&mut cursor)
+    let val = parse_value(&mut cursor, &BinaryOptions::default())?;
+    let rest = &b[cursor.position() as usize..];
+    if rest.is_empty() || rest == footer {
+        Ok(val)
+    } else {
+        Err(anyhow!(
+            "Binary LLSD had {} bytes of unexpected data after the value",
+            rest.len()
+        ))
+    }
 }
 
 ///    Parse LLSD reader expressed in binary into an LLSDObject tree. No header.
 pub fn from_reader(cursor: &mut dyn Read) -> Result<LLSDValue, Error> {
-    parse_value(cursor)
+    from_reader_with_options(cursor, &BinaryOptions::default())
+}
+
+///    Parse LLSD reader expressed in binary into an LLSDObject tree, with options. No header.
+pub fn from_reader_with_options(cursor: &mut dyn Read, options: &BinaryOptions) -> Result<LLSDValue, Error> {
+    parse_value(cursor, options)
+}
+
+///    Parse LLSD reader expressed in binary into an LLSDObject tree, buffering reads. No header.
+///    The parser reads a few bytes at a time, which is slow on an unbuffered
+///    source such as a socket. This reads in large blocks instead.
+///    The buffer may read past the end of the value, so use this only when
+///    nothing else is to be read from the source afterwards.
+///    Input already in memory needs no buffering. Use from_bytes for that.
+pub fn from_reader_buffered(cursor: &mut dyn Read) -> Result<LLSDValue, Error> {
+    from_reader_buffered_with_options(cursor, &BinaryOptions::default())
+}
+
+///    Parse LLSD reader expressed in binary into an LLSDObject tree, buffering reads, with options. No header.
+pub fn from_reader_buffered_with_options(cursor: &mut dyn Read, options: &BinaryOptions) -> Result<LLSDValue, Error> {
+    parse_value(&mut BufReader::with_capacity(READ_BUFFER_SIZE, cursor), options)
+}
+
+///    Parse one length-prefixed frame of binary LLSD from a stream.
+///    The frame is a big-endian u32 byte count, then that many bytes of
+///    binary LLSD, header included. The value must fill the frame exactly.
+///    Written by ser::binary::to_length_prefixed.
+pub fn from_length_prefixed(cursor: &mut dyn Read) -> Result<LLSDValue, Error> {
+    let mut b: [u8; 4] = [0; 4];
+    cursor.read_exact(&mut b)?;
+    let mut frame = Vec::new();
+    read_bytes(cursor, u32::from_be_bytes(b) as usize, &BinaryOptions::default(), &mut frame)?;
+    match frame.strip_prefix(LLSDBINARYSENTINEL) {
+        Some(body) => from_bytes_exact(body),
+        None => Err(anyhow!("Length-prefixed frame does not begin with the binary LLSD header")),
+    }
+}
+
+/// Parse 4 bytes of ASCII text as an integer, for data recovery.
+/// None if this doesn't look like an ASCII number.
+fn parse_ascii_integer(b: &[u8; 4]) -> Option<i32> {
+    if !b.iter().all(|ch| matches!(ch, b'0'..=b'9' | b'-' | b'+' | b' ' | 0)) {
+        return None;
+    }
+    let s = std::str::from_utf8(b).ok()?.trim_matches(|c| c == ' ' || c == '\0');
+    s.parse::<i32>().ok()
+}
+
+/// Read length bytes into buf, replacing its contents.
+/// The length comes from the data, so it is checked against the limit, and
+/// the buffer grows as bytes actually arrive rather than being allocated up front.
+fn read_bytes<R: Read + ?Sized>(cursor: &mut R, length: usize, options: &BinaryOptions, buf: &mut Vec<u8>) -> Result<(), Error> {
+    if length > options.max_alloc {
+        return Err(anyhow!(
+            "Binary LLSD item claims to be {} bytes long, over the limit of {} bytes",
+            length,
+            options.max_alloc
+        ));
+    }
+    buf.clear();
+    let got = cursor.take(length as u64).read_to_end(buf)?;
+    if got != length {
+        return Err(anyhow!(
+            "Binary LLSD item claims to be {} bytes long, but data ended after {} bytes",
+            length,
+            got
+        ));
+    }
+    Ok(())
+}
+
+/// Read length bytes of string and convert to a String, per the string encoding option.
+/// Strings are read into the reusable scratch buffer and converted from there,
+/// so each string costs one allocation, of exactly its own size.
+fn read_string<R: Read + ?Sized>(cursor: &mut R, length: usize, options: &BinaryOptions, scratch: &mut Vec<u8>) -> Result<String, Error> {
+    read_bytes(cursor, length, options, scratch)?;
+    match options.string_encoding {
+        StringEncoding::Utf8 => Ok(std::str::from_utf8(scratch)?.to_owned()),
+        StringEncoding::Latin1 => Ok(scratch.iter().map(|&b| char::from(b)).collect()),
+    }
+}
+
+/// Parse one value - real, integer, map, etc.
+/// Generic over the reader, so parsing from a slice has no virtual call per read.
+/// The public from_reader functions pass a dyn Read through here.
+fn parse_value<R: Read + ?Sized>(cursor: &mut R, options: &BinaryOptions) -> Result<LLSDValue, Error> {
+    let mut scratch = Vec::new();
+    parse_value_scratch(cursor, options, &mut scratch, 0)
 }
 
-/// Parse one value - real, integer, map, etc. Recursive.
-fn parse_value(cursor: &mut dyn Read) -> Result<LLSDValue, Error> {
-    //  These could be generic if generics with numeric parameters were in stable Rust.
-    fn read_u8(cursor: &mut dyn Read) -> Result<u8, Error> {
+/// Parse one value, with a scratch buffer reused across the whole parse. Recursive.
+/// Depth is the number of enclosing maps and arrays.
+fn parse_value_scratch<R: Read + ?Sized>(cursor: &mut R, options: &BinaryOptions, scratch: &mut Vec<u8>, depth: usize) -> Result<LLSDValue, Error> {
+    //  These could be generic over size if generics with numeric parameters were in stable Rust.
+    fn read_u8<R: Read + ?Sized>(cursor: &mut R) -> Result<u8, Error> {
         let mut b: [u8; 1] = [0; 1];
         cursor.read_exact(&mut b)?; // read one byte
         Ok(b[0])
     }
-    fn read_u32(cursor: &mut dyn Read) -> Result<u32, Error> {
+    fn read_u32<R: Read + ?Sized>(cursor: &mut R) -> Result<u32, Error> {
         let mut b: [u8; 4] = [0; 4];
         cursor.read_exact(&mut b)?; // read one byte
         Ok(u32::from_be_bytes(b))
     }
-    fn read_i32(cursor: &mut dyn Read) -> Result<i32, Error> {
+    fn read_i32<R: Read + ?Sized>(cursor: &mut R) -> Result<i32, Error> {
         let mut b: [u8; 4] = [0; 4];
         cursor.read_exact(&mut b)?; // read one byte
         Ok(i32::from_be_bytes(b))
     }
-    fn read_i64(cursor: &mut dyn Read) -> Result<i64, Error> {
+    fn read_date<R: Read + ?Sized>(cursor: &mut R) -> Result<f64, Error> {
         let mut b: [u8; 8] = [0; 8];
-        cursor.read_exact(&mut b)?; // read one byte
-        Ok(i64::from_be_bytes(b))
+        cursor.read_exact(&mut b)?;
+        Ok(f64::from_le_bytes(b))
     }
-    fn read_f64(cursor: &mut dyn Read) -> Result<f64, Error> {
+    fn read_f64<R: Read + ?Sized>(cursor: &mut R) -> Result<f64, Error> {
         let mut b: [u8; 8] = [0; 8];
         cursor.read_exact(&mut b)?; // read one byte
         Ok(f64::from_be_bytes(b))
     }
-    fn read_variable(cursor: &mut dyn Read) -> Result<Vec<u8>, Error> {
+    //  Read through the scratch buffer, then copy out at exactly the right size.
+    fn read_variable<R: Read + ?Sized>(cursor: &mut R, options: &BinaryOptions, scratch: &mut Vec<u8>) -> Result<Vec<u8>, Error> {
         let length = read_u32(cursor)?; // read length in bytes
-        let mut buf = vec![0u8; length as usize];
-        cursor.read_exact(&mut buf)?;
-        Ok(buf) // read bytes of string
+        read_bytes(cursor, length as usize, options, scratch)?;
+        Ok(scratch.to_vec())
     }
 
     let typecode = read_u8(cursor)?;
@@ -76,15 +235,27 @@ fn parse_value(cursor: &mut dyn Read) -> Result<LLSDValue, Error> {
         b'0' => Ok(LLSDValue::Boolean(false)),
         b'1' => Ok(LLSDValue::Boolean(true)),
         //  String - length followed by data
-        b's' => Ok(LLSDValue::String(
-            std::str::from_utf8(&read_variable(cursor)?)?.to_string(),
-        )),
+        b's' => {
+            let length = read_u32(cursor)? as usize;
+            Ok(LLSDValue::String(read_string(cursor, length, options, scratch)?))
+        }
         //  URI - length followed by data
-        b'l' => Ok(LLSDValue::URI(
-            std::str::from_utf8(&read_variable(cursor)?)?.to_string(),
-        )),
+        b'l' => {
+            let length = read_u32(cursor)? as usize;
+            Ok(LLSDValue::URI(read_string(cursor, length, options, scratch)?))
+        }
         //  Integer - 4 bytes
-        b'i' => Ok(LLSDValue::Integer(read_i32(cursor)?)),
+        b'i' => {
+            if options.lenient_ascii_numbers {
+                let mut b: [u8; 4] = [0; 4];
+                cursor.read_exact(&mut b)?;
+                Ok(LLSDValue::Integer(
+                    parse_ascii_integer(&b).unwrap_or_else(|| i32::from_be_bytes(b)),
+                ))
+            } else {
+                Ok(LLSDValue::Integer(read_i32(cursor)?))
+            }
+        }
         //  Real - 4 bytes
         b'r' => Ok(LLSDValue::Real(read_f64(cursor)?)),
         //  UUID - 16 bytes
@@ -94,19 +265,39 @@ fn parse_value(cursor: &mut dyn Read) -> Result<LLSDValue, Error> {
             Ok(LLSDValue::UUID(uuid::Uuid::from_bytes(buf)))
         }
         //  Binary - length followed by data
-        b'b' => Ok(LLSDValue::Binary(read_variable(cursor)?)),
-        //  Date - 64 bits
-        b'd' => Ok(LLSDValue::Date(read_i64(cursor)?)),
+        b'b' => Ok(LLSDValue::Binary(read_variable(cursor, options, scratch)?)),
+        //  Date - 64-bit double, seconds since 1970. Unlike reals, this is little-endian,
+        //  as written by the Linden Lab C++ and Python implementations.
+        b'd' => Ok(LLSDValue::Date(read_date(cursor)?)),
         //  Map -- keyed collection of items
         b'{' => {
-            let mut dict: HashMap<String, LLSDValue> = HashMap::new(); // accumulate hash here
+            crate::de::check_depth(depth, options.max_depth)?;
+            let mut dict = LLSDMap::new(); // accumulate hash here
             let count = read_u32(cursor)?; // number of items
+            //  Keys should begin with b'k'. Some producers omit that prefix
+            //  in some maps but not others. Whatever the first key of a map
+            //  does, the rest of that map must do the same.
+            //  A bare key starts with its 4-byte length, so the first byte is 0.
+            let mut prefixed: Option<bool> = None;
             for _ in 0..count {
-                let keyprefix = &read_u8(cursor)?; // key should begin with b'k';
-                match keyprefix {
-                    b'k' => {
-                        let key = std::str::from_utf8(&read_variable(cursor)?)?.to_string();
-                        let _ = dict.insert(key, parse_value(cursor)?); // recurse and add, allowing dups
+                let keyprefix = read_u8(cursor)?;
+                let length = match (keyprefix, prefixed) {
+                    (b'k', None | Some(true)) => {
+                        prefixed = Some(true);
+                        read_u32(cursor)?
+                    }
+                    (0, None | Some(false)) => {
+                        prefixed = Some(false);
+                        let mut b: [u8; 3] = [0; 3];
+                        cursor.read_exact(&mut b)?;
+                        u32::from_be_bytes([0, b[0], b[1], b[2]])
+                    }
+                    (b'k' | 0, Some(was_prefixed)) => {
+                        return Err(anyhow!(
+                            "Binary LLSD map mixes keys {} the 'k' prefix with keys {} it",
+                            if was_prefixed { "with" } else { "without" },
+                            if was_prefixed { "without" } else { "with" },
+                        ))
                     }
                     _ => {
                         return Err(anyhow!(
@@ -114,22 +305,35 @@ fn parse_value(cursor: &mut dyn Read) -> Result<LLSDValue, Error> {
                             keyprefix
                         ))
                     }
-                }
+                };
+                let key = read_string(cursor, length as usize, options, scratch)?;
+                let _ = dict.insert(key, parse_value_scratch(cursor, options, scratch, depth + 1)?); // recurse and add, allowing dups
             }
-            if read_u8(cursor)? != b'}' {
-                return Err(anyhow!("Binary LLSD map did not end properly with }}"));
+            let terminator = read_u8(cursor)?;
+            if terminator != b'}' {
+                return Err(anyhow!(
+                    "Binary LLSD map did not end properly with }} after its declared {} entries, found {:?}. Count does not match content.",
+                    count,
+                    char::from(terminator)
+                ));
             }
             Ok(LLSDValue::Map(dict))
         }
         //  Array -- array of items
         b'[' => {
+            crate::de::check_depth(depth, options.max_depth)?;
             let mut array: Vec<LLSDValue> = Vec::new(); // accumulate hash here
             let count = read_u32(cursor)?; // number of items
             for _ in 0..count {
-                array.push(parse_value(cursor)?); // recurse and add, allowing dups
+                array.push(parse_value_scratch(cursor, options, scratch, depth + 1)?); // recurse and add, allowing dups
             }
-            if read_u8(cursor)? != b']' {
-                return Err(anyhow!("Binary LLSD array did not end properly with ] "));
+            let terminator = read_u8(cursor)?;
+            if terminator != b']' {
+                return Err(anyhow!(
+                    "Binary LLSD array did not end properly with ] after its declared {} items, found {:?}. Count does not match content.",
+                    count,
+                    char::from(terminator)
+                ));
             }
             Ok(LLSDValue::Array(array))
         }
@@ -140,10 +344,72 @@ fn parse_value(cursor: &mut dyn Read) -> Result<LLSDValue, Error> {
 
 // Unit test
 
+/// Allocator for tests which counts allocations and live bytes for the current thread,
+/// so a test can measure its own parse while other tests run in parallel.
+#[cfg(test)]
+struct CountingAlloc;
+
+#[cfg(test)]
+thread_local! {
+    static ALLOC_COUNT: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    //  Signed, because a thread can free memory another thread allocated.
+    static LIVE_BYTES: std::cell::Cell<isize> = const { std::cell::Cell::new(0) };
+    static PEAK_BYTES: std::cell::Cell<isize> = const { std::cell::Cell::new(0) };
+}
+
+/// Track live bytes, and the high-water mark, for the current thread.
+#[cfg(test)]
+fn add_live_bytes(delta: isize) {
+    let _ = LIVE_BYTES.try_with(|live| {
+        live.set(live.get() + delta);
+        let _ = PEAK_BYTES.try_with(|peak| peak.set(peak.get().max(live.get())));
+    });
+}
+
+#[cfg(test)]
+unsafe impl std::alloc::GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        let _ = ALLOC_COUNT.try_with(|n| n.set(n.get() + 1));
+        add_live_bytes(layout.size() as isize);
+        std::alloc::System.alloc(layout)
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: std::alloc::Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOC_COUNT.try_with(|n| n.set(n.get() + 1));
+        add_live_bytes(new_size as isize - layout.size() as isize);
+        std::alloc::System.realloc(ptr, layout, new_size)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        add_live_bytes(-(layout.size() as isize));
+        std::alloc::System.dealloc(ptr, layout)
+    }
+}
+
+#[cfg(test)]
+#[global_allocator]
+static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+/// Allocations made by the current thread while running f.
+#[cfg(test)]
+fn count_allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOC_COUNT.with(|n| n.get());
+    let result = f();
+    (result, ALLOC_COUNT.with(|n| n.get()) - before)
+}
+
+/// Most heap memory the current thread held at once while running f,
+/// above what it held before. Used by the other parsers' tests too.
+#[cfg(test)]
+pub(crate) fn peak_bytes<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = LIVE_BYTES.with(|live| live.get());
+    PEAK_BYTES.with(|peak| peak.set(before));
+    let result = f();
+    (result, (PEAK_BYTES.with(|peak| peak.get()) - before).max(0) as usize)
+}
+
 #[test]
 fn binaryparsetest1() {
     //  Construct a test value.
-    let test1map: HashMap<String, LLSDValue> = [
+    let test1map: LLSDMap = [
         ("val1".to_string(), LLSDValue::Real(456.0)),
         ("val2".to_string(), LLSDValue::Integer(999)),
     ]
@@ -164,4 +430,321 @@ fn binaryparsetest1() {
     println!("Value after round-trip conversion: {:?}", test1value);
     //  Check that results match after round trip.
     assert_eq!(test1, test1value);
+    //  Every variant.
+    let example = LLSDValue::example();
+    let examplebin = crate::to_bytes(&example).unwrap();
+    assert_eq!(from_bytes(&examplebin[LLSDBINARYSENTINEL.len()..]).unwrap(), example);
+}
+
+#[test]
+fn binaryparseasciinumbertest1() {
+    //  Array of two integers, the first written as ASCII by a buggy producer.
+    let mut test1bin: Vec<u8> = Vec::new();
+    test1bin.extend_from_slice(b"[");
+    test1bin.extend_from_slice(&2u32.to_be_bytes());
+    test1bin.extend_from_slice(b"i1234");
+    test1bin.extend_from_slice(b"i");
+    test1bin.extend_from_slice(&(-5i32).to_be_bytes());
+    test1bin.extend_from_slice(b"]");
+    //  Conforming parse reads the ASCII as big-endian bytes.
+    let strict = from_bytes(&test1bin).unwrap();
+    assert_eq!(strict, LLSDValue::Array(vec![LLSDValue::Integer(0x31323334), LLSDValue::Integer(-5)]));
+    //  Recovery parse reads it as text, and leaves real binary integers alone.
+    let options = BinaryOptions { lenient_ascii_numbers: true, ..Default::default() };
+    let lenient = from_bytes_with_options(&test1bin, &options).unwrap();
+    assert_eq!(lenient, LLSDValue::Array(vec![LLSDValue::Integer(1234), LLSDValue::Integer(-5)]));
+}
+
+#[test]
+fn binaryparseexacttest1() {
+    const FOOTER: &[u8] = b"\0\0END";
+    let test1 = LLSDValue::Array(vec![LLSDValue::Integer(42), LLSDValue::String("Hello".to_string())]);
+    let body = crate::to_bytes(&test1).unwrap()[LLSDBINARYSENTINEL.len()..].to_vec();
+    //  Exact, no footer.
+    assert_eq!(from_bytes_exact(&body).unwrap(), test1);
+    assert_eq!(from_bytes_exact_with_footer(&body, FOOTER).unwrap(), test1);
+    //  Known footer accepted.
+    let mut with_footer = body.clone();
+    with_footer.extend_from_slice(FOOTER);
+    assert_eq!(from_bytes_exact_with_footer(&with_footer, FOOTER).unwrap(), test1);
+    assert!(from_bytes_exact(&with_footer).is_err());
+    //  Random trailing bytes rejected, but lenient from_bytes ignores them.
+    let mut with_junk = body.clone();
+    with_junk.extend_from_slice(b"xyz");
+    assert!(from_bytes_exact_with_footer(&with_junk, FOOTER).is_err());
+    assert_eq!(from_bytes(&with_junk).unwrap(), test1);
+}
+
+#[test]
+fn binaryparsecounttest1() {
+    //  Map declaring 1 entry, but containing 2.
+    let mut test1bin: Vec<u8> = Vec::new();
+    test1bin.extend_from_slice(b"{");
+    test1bin.extend_from_slice(&1u32.to_be_bytes());
+    for key in [b"a", b"b"] {
+        test1bin.extend_from_slice(b"k");
+        test1bin.extend_from_slice(&1u32.to_be_bytes());
+        test1bin.extend_from_slice(key);
+        test1bin.extend_from_slice(b"!");
+    }
+    test1bin.extend_from_slice(b"}");
+    let err = from_bytes(&test1bin).unwrap_err().to_string();
+    assert!(err.contains("declared 1 entries"), "{}", err);
+    //  Array declaring 1 item, but containing 2.
+    let mut test2bin: Vec<u8> = Vec::new();
+    test2bin.extend_from_slice(b"[");
+    test2bin.extend_from_slice(&1u32.to_be_bytes());
+    test2bin.extend_from_slice(b"01]");
+    let err = from_bytes(&test2bin).unwrap_err().to_string();
+    assert!(err.contains("declared 1 items"), "{}", err);
+}
+
+#[test]
+fn binaryparsekeyheavytest1() {
+    //  Map with many keys and string values, round trip.
+    //  Each key and each string should cost one allocation, for the String itself,
+    //  plus a few for the map's table as it grows.
+    let count = 1000;
+    let test1: LLSDValue = LLSDValue::Map(
+        (0..count)
+            .map(|n| (format!("key{}", n), LLSDValue::String(format!("value☺{}", n))))
+            .collect(),
+    );
+    let test1bin = crate::to_bytes(&test1).unwrap();
+    let (parsed, allocs) = count_allocations(|| from_bytes(&test1bin[LLSDBINARYSENTINEL.len()..]).unwrap());
+    assert_eq!(parsed, test1);
+    assert!(allocs < 2 * count + count / 4, "{} allocations for {} keys", allocs, count);
+    //  Invalid UTF-8 in a key is still an error.
+    let mut test2bin: Vec<u8> = Vec::new();
+    test2bin.extend_from_slice(b"{");
+    test2bin.extend_from_slice(&1u32.to_be_bytes());
+    test2bin.extend_from_slice(b"k");
+    test2bin.extend_from_slice(&1u32.to_be_bytes());
+    test2bin.extend_from_slice(&[0xff, b'!', b'}']);
+    assert!(from_bytes(&test2bin).is_err());
+}
+
+#[test]
+fn binaryparselatin1test1() {
+    //  String with bytes 0x80..0xFF, not valid UTF-8.
+    let raw: Vec<u8> = (0x80..=0xff).collect();
+    let mut test1bin: Vec<u8> = Vec::new();
+    test1bin.extend_from_slice(b"s");
+    test1bin.extend_from_slice(&(raw.len() as u32).to_be_bytes());
+    test1bin.extend_from_slice(&raw);
+    assert!(from_bytes(&test1bin).is_err()); // UTF-8 is the default
+    let options = BinaryOptions { string_encoding: StringEncoding::Latin1, ..Default::default() };
+    let parsed = from_bytes_with_options(&test1bin, &options).unwrap();
+    let s = parsed.as_string().unwrap();
+    assert_eq!(s.chars().count(), raw.len());
+    assert!(s.chars().zip(raw.iter()).all(|(c, b)| c as u32 == *b as u32));
+    assert!(s.starts_with('\u{80}') && s.ends_with('ÿ'));
+}
+
+#[test]
+fn binaryparsebarekeytest1() {
+    //  Build a map body by hand, with or without 'k' on each key.
+    fn map_bytes(prefixes: &[bool]) -> Vec<u8> {
+        let mut b = vec![b'{'];
+        b.extend_from_slice(&(prefixes.len() as u32).to_be_bytes());
+        for (i, &prefixed) in prefixes.iter().enumerate() {
+            if prefixed {
+                b.push(b'k');
+            }
+            b.extend_from_slice(&1u32.to_be_bytes());
+            b.push(b'a' + i as u8);
+            b.push(b'i');
+            b.extend_from_slice(&(i as i32).to_be_bytes());
+        }
+        b.push(b'}');
+        b
+    }
+    let expected = crate::notation_from_str("{'a':i0,'b':i1}").unwrap();
+    //  Consistently bare.
+    assert_eq!(from_bytes(&map_bytes(&[false, false])).unwrap(), expected);
+    //  Consistently prefixed.
+    assert_eq!(from_bytes(&map_bytes(&[true, true])).unwrap(), expected);
+    //  Inconsistent, either way round.
+    assert!(from_bytes(&map_bytes(&[true, false])).is_err());
+    assert!(from_bytes(&map_bytes(&[false, true])).is_err());
+    //  A bare top-level map holding a prefixed nested map is fine.
+    let mut b = vec![b'{', 0, 0, 0, 1, 0, 0, 0, 1, b'n'];
+    b.extend_from_slice(&map_bytes(&[true, true]));
+    b.push(b'}');
+    let val = from_bytes(&b).unwrap();
+    assert_eq!(val.as_map().unwrap()["n"], expected);
+}
+
+#[test]
+fn binaryparsesmallstringstest1() {
+    //  Large array of small strings, the case the scratch buffer is for.
+    let test1 = LLSDValue::Array((0..10000).map(|n| LLSDValue::String(format!("caf\u{e9} {}", n))).collect());
+    let test1bin = crate::to_bytes(&test1).unwrap();
+    assert_eq!(from_bytes(&test1bin[LLSDBINARYSENTINEL.len()..]).unwrap(), test1);
+    //  As Latin-1, each UTF-8 byte becomes one char.
+    let options = BinaryOptions { string_encoding: StringEncoding::Latin1, ..Default::default() };
+    let parsed = from_bytes_with_options(&test1bin[LLSDBINARYSENTINEL.len()..], &options).unwrap();
+    let parsed = parsed.as_array().unwrap();
+    assert_eq!(parsed.len(), 10000);
+    assert_eq!(parsed[42], LLSDValue::String("caf\u{c3}\u{a9} 42".to_string()));
+}
+
+#[test]
+fn binaryparseallocationstest1() {
+    //  Each string, key, and binary leaf should cost one allocation, for the value itself.
+    //  Reads go through the scratch buffer, so nothing else is allocated per leaf.
+    let count = 2000;
+    let leaf = "x".repeat(200);
+    let test1 = LLSDValue::Array(
+        (0..count)
+            .map(|n| {
+                LLSDValue::Map(
+                    [
+                        (format!("name{}", n), LLSDValue::String(leaf.clone())),
+                        ("data".to_string(), LLSDValue::Binary(leaf.clone().into_bytes())),
+                    ]
+                    .into_iter()
+                    .collect(),
+                )
+            })
+            .collect(),
+    );
+    let test1bin = crate::to_bytes(&test1).unwrap();
+    let body = &test1bin[LLSDBINARYSENTINEL.len()..];
+    //  Per map: two keys, a string, a binary, and the map's own table.
+    //  A few more for the outer array and the scratch buffer growing.
+    let expected = count * 4 + count;
+    for encoding in [StringEncoding::Utf8, StringEncoding::Latin1] {
+        let options = BinaryOptions { string_encoding: encoding, ..Default::default() };
+        let (parsed, allocs) = count_allocations(|| from_bytes_with_options(body, &options).unwrap());
+        assert_eq!(parsed, test1);
+        assert!(allocs <= expected + 32, "{:?}: {} allocations, expected about {}", encoding, allocs, expected);
+    }
+}
+
+#[test]
+fn binaryparsedepthtest1() {
+    use crate::de::DEFAULT_MAX_DEPTH;
+    //  Arrays of one item, nested n deep, with an empty array innermost.
+    fn nested(n: usize) -> Vec<u8> {
+        let mut b = Vec::new();
+        for i in 0..n {
+            b.push(b'[');
+            b.extend_from_slice(&(if i + 1 < n { 1u32 } else { 0u32 }).to_be_bytes());
+        }
+        b.resize(b.len() + n, b']');
+        b
+    }
+    assert!(from_bytes(&nested(DEFAULT_MAX_DEPTH)).is_ok());
+    let err = from_bytes(&nested(DEFAULT_MAX_DEPTH + 1)).unwrap_err();
+    assert!(err.to_string().contains("maximum nesting depth exceeded"));
+    //  Hostile input is an error, not a stack overflow.
+    assert!(from_bytes(&nested(1_000_000)).is_err());
+    let options = BinaryOptions { max_depth: 3, ..Default::default() };
+    assert!(from_bytes_with_options(&nested(3), &options).is_ok());
+    assert!(from_bytes_with_options(&nested(4), &options).is_err());
+}
+
+#[test]
+fn binaryparsemaxalloctest1() {
+    //  An 18-byte message claiming a 4 GB string fails cleanly.
+    let mut b = vec![b's'];
+    b.extend_from_slice(&u32::MAX.to_be_bytes());
+    b.extend_from_slice(b"short string!");
+    let err = from_bytes(&b).unwrap_err();
+    assert!(err.to_string().contains("over the limit"), "{}", err);
+    //  Under the limit, but the data runs out.
+    let options = BinaryOptions { max_alloc: usize::MAX, ..Default::default() };
+    let err = from_bytes_with_options(&b, &options).unwrap_err();
+    assert!(err.to_string().contains("data ended after 13 bytes"), "{}", err);
+    //  Binary items and keys are limited too.
+    let options = BinaryOptions { max_alloc: 4, ..Default::default() };
+    let mut b = vec![b'b'];
+    b.extend_from_slice(&5u32.to_be_bytes());
+    b.extend_from_slice(b"12345");
+    assert!(from_bytes(&b).is_ok());
+    assert!(from_bytes_with_options(&b, &options).is_err());
+    let mut b = vec![b'{'];
+    b.extend_from_slice(&1u32.to_be_bytes());
+    b.push(b'k');
+    b.extend_from_slice(&5u32.to_be_bytes());
+    b.extend_from_slice(b"abcde!}");
+    assert!(from_bytes(&b).is_ok());
+    assert!(from_bytes_with_options(&b, &options).is_err());
+    //  Length-prefixed frames are checked before allocation.
+    let mut frame = Cursor::new(u32::MAX.to_be_bytes().to_vec());
+    assert!(from_length_prefixed(&mut frame).unwrap_err().to_string().contains("over the limit"));
+}
+
+#[test]
+fn binaryparseonebytetest1() {
+    //  A reader which returns one byte per read call, so every
+    //  length field, UUID, and string spans read boundaries.
+    struct OneByte<'a>(&'a [u8]);
+    impl Read for OneByte<'_> {
+        fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+            match (self.0.split_first(), out.first_mut()) {
+                (Some((b, rest)), Some(o)) => {
+                    *o = *b;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+    let val = LLSDValue::example();
+    let b = crate::ser::binary::to_bytes(&val).unwrap();
+    let body = &b[LLSDBINARYPREFIX.len()..];
+    assert_eq!(from_reader(&mut OneByte(body)).unwrap(), val);
+    let options = BinaryOptions { string_encoding: StringEncoding::Latin1, ..Default::default() };
+    let ascii = LLSDValue::Array(vec![LLSDValue::String("Latin-1 path".to_string()), LLSDValue::UUID(uuid::Uuid::from_u128(0x0123456789abcdef))]);
+    let b = crate::ser::binary::to_bytes(&ascii).unwrap();
+    assert_eq!(from_reader_with_options(&mut OneByte(&b[LLSDBINARYPREFIX.len()..]), &options).unwrap(), ascii);
+    let framed = crate::ser::binary::to_length_prefixed(&val).unwrap();
+    assert_eq!(from_length_prefixed(&mut OneByte(&framed)).unwrap(), val);
+    //  Truncated input is still an error.
+    assert!(from_reader(&mut OneByte(&body[..body.len() - 1])).is_err());
+}
+
+#[test]
+fn binaryparsebufferedtest1() {
+    //  Counts read calls on the underlying source.
+    struct Counting<'a> {
+        data: &'a [u8],
+        reads: usize,
+    }
+    impl Read for Counting<'_> {
+        fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+            self.reads += 1;
+            self.data.read(out)
+        }
+    }
+    let val = LLSDValue::example();
+    let b = crate::ser::binary::to_bytes(&val).unwrap();
+    let body = &b[LLSDBINARYPREFIX.len()..];
+    let mut unbuffered = Counting { data: body, reads: 0 };
+    assert_eq!(from_reader(&mut unbuffered).unwrap(), val);
+    let mut buffered = Counting { data: body, reads: 0 };
+    assert_eq!(from_reader_buffered(&mut buffered).unwrap(), val);
+    assert!(buffered.reads <= 2, "{} reads", buffered.reads); // one block, maybe one more to find EOF
+    assert!(unbuffered.reads > 20 * buffered.reads);
+    //  Errors still come through.
+    let mut short = Counting { data: &body[..body.len() - 1], reads: 0 };
+    assert!(from_reader_buffered(&mut short).is_err());
+}
+
+
+#[test]
+fn binaryparsedatetest1() {
+    //  Dates are little-endian doubles, and keep fractional seconds.
+    let val = LLSDValue::Array(vec![LLSDValue::Date(1138804193.25), LLSDValue::Date(-0.5)]);
+    let b = crate::ser::binary::to_bytes(&val).unwrap();
+    let body = &b[LLSDBINARYPREFIX.len()..];
+    assert_eq!(body[5], b'd');
+    assert_eq!(body[6..14], 1138804193.25f64.to_le_bytes());
+    assert_eq!(from_bytes(body).unwrap(), val);
+    //  As written by the Linden Lab implementations: 'd' then 2006-02-01T14:29:53Z, little-endian.
+    let msg = [b'd', 0, 0, 0x40, 0x78, 0x31, 0xf8, 0xd0, 0x41];
+    assert_eq!(from_reader(&mut &msg[..]).unwrap(), LLSDValue::Date(1138804193.0));
 }
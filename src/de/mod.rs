@@ -2,8 +2,18 @@
 pub mod binary;
 pub mod xml;
 pub mod notation;
+pub mod serde_impl;
 
 use anyhow::{anyhow, Error};
+use crate::util::trim_ascii_start;
+use crate::Format;
+use std::path::Path;
+
+/// Default limit on nesting of maps and arrays, for all parsers.
+/// Deeper input is rejected rather than risking a stack overflow.
+/// Parsers recurse, and debug builds use several KB of stack per level,
+/// so this stays well inside a 2 MB thread stack. Real LLSD is rarely 20 deep.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
 
 /// Parse LLSD, detecting format.
 /// Recognizes Notation, and XML LLSD with sentinels.
@@ -32,12 +42,18 @@ pub fn auto_from_str(msg_string: &str) -> Result<crate::LLSDValue, Error> {
 /// Recognizes binary, Notation, and XML LLSD, with or without sentinel.
 /// Will accept leading whitespace for text forms, but not binary. That's strict.
 pub fn auto_from_bytes(msg: &[u8]) -> Result<crate::LLSDValue, Error> {
+    Ok(auto_from_bytes_with_format(msg)?.0)
+}
+
+/// Parse LLSD, detecting format, and return the format detected.
+/// A proxy can use that to re-serialize in the format it received.
+pub fn auto_from_bytes_with_format(msg: &[u8]) -> Result<(crate::LLSDValue, Format), Error> {
     //  Try sentinels first.
     //  Binary sentinel
     if msg.len() >= binary::LLSDBINARYSENTINEL.len()
         && &msg[0..binary::LLSDBINARYSENTINEL.len()] == binary::LLSDBINARYSENTINEL
     {
-        return binary::from_bytes(&msg[binary::LLSDBINARYSENTINEL.len()..]);
+        return Ok((binary::from_bytes(&msg[binary::LLSDBINARYSENTINEL.len()..])?, Format::Binary));
     }
     //  For text forms, tolerate leading whitespace.      
     {   let msg = trim_ascii_start(msg);               // remove leading whitespace if any
@@ -46,20 +62,20 @@ pub fn auto_from_bytes(msg: &[u8]) -> Result<crate::LLSDValue, Error> {
         if msg.len() >= sentinel.len()
             && &msg[0..sentinel.len()] == sentinel
         {
-            return notation::from_bytes(&msg[sentinel.len()..]);
+            return Ok((notation::from_bytes(&msg[sentinel.len()..])?, Format::Notation));
         }
         //  Try XML sentinel.
         let msgstring = std::str::from_utf8(msg)?; // convert to UTF-8 string
         if msgstring.trim_start().starts_with(xml::LLSDXMLSENTINEL) {
         // try XML
-            return xml::from_str(msgstring);
+            return Ok((xml::from_str(msgstring)?, Format::Xml));
         }
     }   
     //  Check for binary without header. If array or map marker, parse.
     if msg.len() > 1 {
         match msg[0] {
             // check first char
-            b'{' | b'[' => return binary::from_bytes(msg),
+            b'{' | b'[' => return Ok((binary::from_bytes(msg)?, Format::Binary)),
             _ => {}
         }
     }
@@ -73,20 +89,75 @@ pub fn auto_from_bytes(msg: &[u8]) -> Result<crate::LLSDValue, Error> {
     Err(anyhow!("LLSD format not recognized: {:?}", snippet))
 }
 
-/// Trim ASCII whitespace from string. 
-/// From an unstable Rust feature soon to become standard.
-fn trim_ascii_start(b: &[u8]) -> &[u8] {
-    let mut bytes = b;
-    while let [first, rest @ ..] = bytes {
-        if first.is_ascii_whitespace() {
-            bytes = rest;
-        } else {
-            break;
+/// LLSD format from an HTTP Content-Type, such as "application/llsd+xml".
+/// Parameters such as "; charset=utf-8" are ignored. None if not an LLSD type.
+pub fn format_from_content_type(ct: &str) -> Option<Format> {
+    let mime = ct.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    match mime.as_str() {
+        "application/llsd+xml" => Some(Format::Xml),
+        "application/llsd+binary" => Some(Format::Binary),
+        "application/llsd+notation" => Some(Format::Notation),
+        _ => None,
+    }
+}
+
+/// Parse an RFC 3339 date into seconds since the UNIX epoch, fractional seconds included.
+/// If lenient, accept a space instead of 'T' between date and time,
+/// and take a date with no time zone offset as UTC.
+pub(crate) fn parse_rfc3339_date(s: &str, lenient: bool) -> Result<f64, Error> {
+    fn secs(date: chrono::DateTime<chrono::FixedOffset>) -> f64 {
+        date.timestamp() as f64 + date.timestamp_subsec_nanos() as f64 / 1.0e9
+    }
+    if !lenient {
+        return Ok(secs(chrono::DateTime::parse_from_rfc3339(s)?));
+    }
+    let mut date = s.trim().replacen(' ', "T", 1);
+    //  Offset is Z, or a sign in the time part.
+    let has_offset = match date.find('T') {
+        Some(pos) => date[pos..].contains(['Z', 'z', '+', '-']),
+        None => false,
+    };
+    if !has_offset {
+        date.push('Z');
+    }
+    Ok(secs(chrono::DateTime::parse_from_rfc3339(&date)?))
+}
+
+/// Check nesting depth before descending into a map or array.
+pub(crate) fn check_depth(depth: usize, max_depth: usize) -> Result<(), Error> {
+    if depth >= max_depth {
+        Err(anyhow!("LLSD maximum nesting depth exceeded. Limit is {} levels.", max_depth))
+    } else {
+        Ok(())
+    }
+}
+
+/// Parse a delta written by ser::to_delta, detecting format, into edits for LLSDValue::apply_patch.
+/// Removals come first, then sets.
+pub fn delta_from_bytes(msg: &[u8]) -> Result<Vec<crate::PatchOp>, Error> {
+    let delta = auto_from_bytes(msg)?;
+    let mut ops = Vec::new();
+    if let Some(remove) = delta.get("remove") {
+        let paths = remove.as_array().ok_or_else(|| anyhow!("Delta \"remove\" is not an array"))?;
+        for path in paths {
+            let path = path.as_string().ok_or_else(|| anyhow!("Delta \"remove\" path is not a string"))?;
+            ops.push(crate::PatchOp::Remove { path: path.clone() });
         }
     }
-    bytes
+    if let Some(set) = delta.get("set") {
+        let set = set.as_map().ok_or_else(|| anyhow!("Delta \"set\" is not a map"))?;
+        for (path, value) in set {
+            ops.push(crate::PatchOp::Set { path: path.clone(), value: value.clone() });
+        }
+    }
+    Ok(ops)
 }
 
+/// Read an LLSD file, detecting format.
+/// The file must have a header unless it is binary starting with a map or array.
+pub fn read_file(path: impl AsRef<Path>) -> Result<crate::LLSDValue, Error> {
+    auto_from_bytes(&std::fs::read(path)?)
+}
 
 #[test]
 fn testpbrmaterialdecode() {
@@ -140,3 +211,43 @@ fn testnotationdetect1() {
     ////let b = crate::notation_to_bytes(&parsed_ba).unwrap();
     ////assert_eq!(TESTNOTATION1A.as_bytes(), b);         // must match correct form
 }
+
+#[test]
+fn testfileroundtrip() {
+    use crate::{Format, LLSDValue};
+    let val = LLSDValue::example();
+    for format in [Format::Xml, Format::Binary, Format::Notation] {
+        let path = std::env::temp_dir().join(format!("serde-llsd-test-{}-{:?}.llsd", std::process::id(), format));
+        crate::ser::write_file(&path, &val, format).unwrap();
+        let read_back = read_file(&path);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(read_back.unwrap(), val);
+    }
+}
+
+#[test]
+fn testautoformat() {
+    const TESTNOTATION: &str = "<? llsd/notation ?>\n[i1,'a']";
+    let (val, format) = auto_from_bytes_with_format(TESTNOTATION.as_bytes()).unwrap();
+    assert_eq!(format, Format::Notation);
+    //  Re-serialize in the same format.
+    let mut out = Vec::new();
+    crate::ser::to_writer_with_format(&mut out, &val, format).unwrap();
+    assert!(out.starts_with(notation::LLSDNOTATIONPREFIX.as_bytes()));
+    assert_eq!(auto_from_bytes_with_format(&out).unwrap(), (val.clone(), Format::Notation));
+    let xml = crate::to_string(&val, false).unwrap();
+    assert_eq!(auto_from_bytes_with_format(xml.as_bytes()).unwrap().1, Format::Xml);
+    let bin = crate::to_bytes(&val).unwrap();
+    assert_eq!(auto_from_bytes_with_format(&bin).unwrap().1, Format::Binary);
+    assert_eq!(auto_from_bytes_with_format(&bin[binary::LLSDBINARYSENTINEL.len()..]).unwrap().1, Format::Binary);
+}
+
+#[test]
+fn testformatfromcontenttype() {
+    assert_eq!(format_from_content_type("application/llsd+xml"), Some(Format::Xml));
+    assert_eq!(format_from_content_type("application/llsd+binary"), Some(Format::Binary));
+    assert_eq!(format_from_content_type("application/llsd+notation"), Some(Format::Notation));
+    assert_eq!(format_from_content_type("Application/LLSD+XML; charset=utf-8"), Some(Format::Xml));
+    assert_eq!(format_from_content_type("application/json"), None);
+    assert_eq!(format_from_content_type(""), None);
+}
@@ -21,13 +21,11 @@
 //  June, 2023.
 //  License: LGPL.
 //
-use crate::LLSDValue;
+use crate::{LLSDMap, LLSDValue};
 use anyhow::{anyhow, Error};
-use std::collections::HashMap;
 use core::iter::{Peekable};
 use core::str::{Chars, Bytes};
 use uuid::{Uuid};
-use chrono::DateTime;
 use base64::Engine;
 
 //
@@ -38,14 +36,62 @@ pub const LLSDNOTATIONPREFIX: &str = "<? llsd/notation ?>\n";
 /// Sentinel, must match exactly.
 pub const LLSDNOTATIONSENTINEL: &str = LLSDNOTATIONPREFIX;
 
+/// Options for parsing notation LLSD.
+#[derive(Debug, Clone)]
+pub struct NotationOptions {
+    /// Accept dates with a space instead of 'T', and with no
+    /// time zone offset, which is taken as UTC. SL sometimes emits these.
+    pub lenient_dates: bool,
+    /// Reject a map with the same key twice.
+    /// Otherwise, the last value for the key wins.
+    pub error_on_duplicate_keys: bool,
+    /// Accept a C-style 'f' or 'F' suffix on reals, as in "r1.5f".
+    /// Hand-written notation copied from code sometimes has these.
+    pub accept_float_suffix: bool,
+    /// Maximum nesting of maps and arrays.
+    pub max_depth: usize,
+}
+
+impl Default for NotationOptions {
+    fn default() -> Self {
+        NotationOptions {
+            lenient_dates: false,
+            error_on_duplicate_keys: false,
+            accept_float_suffix: false,
+            max_depth: crate::de::DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
 /// Exported parse from bytes.
 pub fn from_bytes(b: &[u8]) -> Result<LLSDValue, Error> {
-    LLSDStreamBytes::parse(b)
+    from_bytes_with_options(b, &NotationOptions::default())
+}
+
+/// Exported parse from bytes, with options.
+/// The "<? llsd/notation ?>" header is optional.
+pub fn from_bytes_with_options(b: &[u8], options: &NotationOptions) -> Result<LLSDValue, Error> {
+    let sentinel = LLSDNOTATIONSENTINEL.trim_end().as_bytes(); // tolerate missing newline
+    let b = match crate::util::trim_ascii_start(b).strip_prefix(sentinel) {
+        Some(rest) => rest,
+        None => b,
+    };
+    LLSDStreamBytes::parse(b, options)
 }
 
 /// Exported parse from str.
 pub fn from_str(s: &str) -> Result<LLSDValue, Error> {
-    LLSDStreamChars::parse(s)
+    from_str_with_options(s, &NotationOptions::default())
+}
+
+/// Exported parse from str, with options.
+/// The "<? llsd/notation ?>" header is optional.
+pub fn from_str_with_options(s: &str, options: &NotationOptions) -> Result<LLSDValue, Error> {
+    let s = match s.trim_start().strip_prefix(LLSDNOTATIONSENTINEL.trim_end()) {
+        Some(rest) => rest,
+        None => s,
+    };
+    LLSDStreamChars::parse(s, options)
 }
 
 /// An LLSD stream. May be either a UTF-8 stream or a byte stream.
@@ -77,13 +123,16 @@ trait LLSDStream<C, S> {
     
     /// Convert into char
     fn into_char(ch: &C) -> char;
+
+    /// Parse options
+    fn options(&self) -> &NotationOptions;
     
     /// Consume whitespace. Next char will be non-whitespace.
     //  Need to treat explicit "\n" as whitespace.
     fn consume_whitespace(&mut self) -> Result<(), Error> {
         while let Some(ch) = self.peek() {
             match Self::into_char(ch) {
-                ' ' | '\n' => { let _ = self.next(); },                 // ignore leading white space
+                ' ' | '\n' | '\t' | '\r' | '\x0c' => { let _ = self.next(); },  // ignore leading ASCII white space
                 '\\' => {
                     let _ = self.next();                                // consume backslash
                     let ch = Self::into_char(&self.next_ok()?);         // expecting 'n'
@@ -121,18 +170,42 @@ trait LLSDStream<C, S> {
         //  Digits accmulated, use standard conversion
         Ok(LLSDValue::Integer(s.parse::<i32>()?))
     }
-        /// Parse "rNNN".
-    //  Does "notation" allow exponents?
+    /// Parse "rNNN". Exponents, as in "r6.022e23", are allowed.
     fn parse_real(&mut self) -> Result<LLSDValue, Error> {
         let mut s = String::with_capacity(20);  // pre-allocate; can still grow
-        //  Accumulate numeric chars.
-        //  This will not accept NaN.
+        //  Sign, if any.
         while let Some(ch) = self.peek() {
             match Self::into_char(ch) {
-                '0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'|'+'|'-'|'.' => s.push(Self::into_char(&self.next().unwrap())),
+                '+'|'-' => s.push(Self::into_char(&self.next().unwrap())),
                  _ => break
             }
         }
+        //  "nan", "inf", or "infinity", in any case, as SL writes non-finite reals.
+        if let Some(ch) = self.peek() {
+            if matches!(Self::into_char(ch), 'n'|'N'|'i'|'I') {
+                while let Some(ch) = self.peek() {
+                    if !Self::into_char(ch).is_ascii_alphabetic() { break }
+                    s.push(Self::into_char(&self.next().unwrap()));
+                }
+                return Ok(LLSDValue::Real(s.parse::<f64>()?))
+            }
+        }
+        //  Accumulate numeric chars. The exponent sign is covered by '+' and '-'.
+        while let Some(ch) = self.peek() {
+            match Self::into_char(ch) {
+                '0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9'|'+'|'-'|'.'|'e'|'E' => s.push(Self::into_char(&self.next().unwrap())),
+                 _ => break
+            }
+        }
+        //  Optional "f" suffix.
+        if let Some(ch) = self.peek() {
+            if matches!(Self::into_char(ch), 'f' | 'F') {
+                if !self.options().accept_float_suffix {
+                    return Err(anyhow!("Real value \"{}\" has an 'f' suffix, which is not allowed unless accept_float_suffix is set", s));
+                }
+                let _ = self.next();
+            }
+        }
         //  Digits accmulated, use standard conversion
         Ok(LLSDValue::Real(s.parse::<f64>()?))
     }
@@ -186,13 +259,13 @@ trait LLSDStream<C, S> {
         if let Some(delim) = self.next() {
             if Self::into_char(&delim) == '"' || Self::into_char(&delim) == '\'' {
                 let s = self.parse_quoted_string(Self::into_char(&delim))?;
-                let naive_date =  DateTime::parse_from_rfc3339(&s)?; // parse date per RFC 3339.
-                Ok(LLSDValue::Date(naive_date.timestamp())) // seconds since UNIX epoch.
+                let secs = crate::de::parse_rfc3339_date(&s, self.options().lenient_dates)?; // parse date per RFC 3339.
+                Ok(LLSDValue::Date(secs)) // seconds since UNIX epoch.
             } else {
-                Err(anyhow!("URI did not begin with '\"'"))
+                Err(anyhow!("Date did not begin with '\"'"))
             }
         } else {
-            Err(anyhow!("URI at end of file."))
+            Err(anyhow!("Date at end of file."))
         }
     }
     
@@ -220,8 +293,8 @@ trait LLSDStream<C, S> {
     }
 
     /// Parse "{ 'key' : value, 'key' : value ... }
-    fn parse_map(&mut self) -> Result<LLSDValue, Error> {
-        let mut kvmap = HashMap::new();                         // building map
+    fn parse_map(&mut self, depth: usize) -> Result<LLSDValue, Error> {
+        let mut kvmap = LLSDMap::new();                         // building map
         loop {
             self.consume_whitespace()?;
             let key =  {
@@ -233,8 +306,11 @@ trait LLSDStream<C, S> {
                 }
             };
             self.consume_char(':')?;
-            let value = self.parse_value()?;           // value of key:value
-            kvmap.insert(key, value);
+            let value = self.parse_value(depth)?;           // value of key:value
+            if self.options().error_on_duplicate_keys && kvmap.contains_key(&key) {
+                return Err(anyhow!("Duplicate map key {:?}", key));
+            }
+            kvmap.insert(key, value);                   // last one wins
             //  Check for comma indicating more items.
             self.consume_whitespace()?;
             if Self::into_char(self.peek_ok()?) == ',' {
@@ -247,7 +323,7 @@ trait LLSDStream<C, S> {
     /// Parse "[ value, value ... ]"
     /// At this point, the '[' has been consumed.
     /// At successful return, the ending ']' has been consumed.
-    fn parse_array(&mut self) -> Result<LLSDValue, Error> {
+    fn parse_array(&mut self, depth: usize) -> Result<LLSDValue, Error> {
         let mut array_items = Vec::new();
         //  Accumulate array elements.
         loop {
@@ -257,7 +333,7 @@ trait LLSDStream<C, S> {
             if ch == ']' {
                 let _ = self.next(); break;    // end of array, may be empty.
             }
-            array_items.push(self.parse_value()?);          // parse next value
+            array_items.push(self.parse_value(depth)?);          // parse next value
             //  Check for comma indicating more items.
             self.consume_whitespace()?;
             if Self::into_char(self.peek_ok()?) == ',' {
@@ -268,13 +344,40 @@ trait LLSDStream<C, S> {
     }
     
     fn parse_binary(&mut self) -> Result<LLSDValue, Error>; // passed down to next level
+
+    /// Parse encoded binary value, b16"value" or b64"value", after the 'b'.
+    /// These are pure ASCII, so they work in both the byte and UTF-8 forms.
+    fn parse_encoded_binary(&mut self) -> Result<LLSDValue, Error> {
+        let ch = Self::into_char(self.peek_ok()?);
+        match ch {
+            '1' => {
+                self.consume_char('1')?;
+                self.consume_char('6')?;          // base 16
+                self.consume_char('"')?;          // begin quote
+                let mut s = self.parse_quoted_string('"')?;
+                s.retain(|c| !c.is_whitespace());
+                Ok(LLSDValue::Binary(hex::decode(s)?))
+            }
+            '6' => {
+                self.consume_char('6')?;
+                self.consume_char('4')?;
+                self.consume_char('"')?;          // begin quote
+                let mut s = self.parse_quoted_string('"')?;
+                s.retain(|c| !c.is_whitespace());
+                let bytes = base64::engine::general_purpose::STANDARD.decode(s)?;
+                Ok(LLSDValue::Binary(bytes))
+            }
+            _ => Err(anyhow!("Binary value started with {} instead of (, 1, or 6", ch))   
+        }
+    }
     
     fn parse_sized_string(&mut self) -> Result<LLSDValue, Error>; // passed down to next level
         
     
     /// Parse one value - real, integer, map, etc. Recursive.
-    /// This is the top level of the parser
-    fn parse_value(&mut self) -> Result<LLSDValue, Error> {
+    /// This is the top level of the parser.
+    /// Depth is the number of enclosing maps and arrays.
+    fn parse_value(&mut self, depth: usize) -> Result<LLSDValue, Error> {
         self.consume_whitespace()?;                      // ignore leading white space
         let ch = Self::into_char(&self.next_ok()?);
         match ch {
@@ -283,8 +386,14 @@ trait LLSDStream<C, S> {
             '1' => { Ok(LLSDValue::Boolean(true)) }     // true
             'f' | 'F' => { self.parse_boolean(ch) }     // false, all alpha forms
             't' | 'T' => { self.parse_boolean(ch) }     // true, all alpha forms
-            '{' => { self.parse_map() }                 // map
-            '[' => { self.parse_array() }               // array
+            '{' => {                                    // map
+                crate::de::check_depth(depth, self.options().max_depth)?;
+                self.parse_map(depth + 1)
+            }
+            '[' => {                                    // array
+                crate::de::check_depth(depth, self.options().max_depth)?;
+                self.parse_array(depth + 1)
+            }
             'i' => { self.parse_integer() }             // integer
             'r' => { self.parse_real() }                // real
             'd' => { self.parse_date() }                // date
@@ -304,6 +413,8 @@ trait LLSDStream<C, S> {
 struct LLSDStreamChars<'a> {
     /// Stream is composed of peekable UTF-8 chars
     cursor: Peekable<Chars<'a>>,
+    /// Parse options
+    options: &'a NotationOptions,
 }
 
 impl LLSDStream<char, Peekable<Chars<'_>>> for LLSDStreamChars<'_> {
@@ -319,10 +430,20 @@ impl LLSDStream<char, Peekable<Chars<'_>>> for LLSDStreamChars<'_> {
     fn into_char(ch: &char) -> char {
         *ch
     }  
+
+    /// Parse options
+    fn options(&self) -> &NotationOptions {
+        self.options
+    }
     
-    /// Won't work.
+    /// Parse binary value. Only b16"value" and b64"value" are allowed.
+    /// Byte-counted b(cnt)"value" won't work inside UTF-8.
     fn parse_binary(&mut self) -> Result<LLSDValue, Error> {
-        Err(anyhow!("Byte-counted binary data inside UTF-8 won't work."))
+        if Self::into_char(self.peek_ok()?) == '(' {
+            Err(anyhow!("Byte-counted binary data inside UTF-8 won't work."))
+        } else {
+            self.parse_encoded_binary()
+        }
     }
     
     /// Won't work.
@@ -334,9 +455,9 @@ impl LLSDStream<char, Peekable<Chars<'_>>> for LLSDStreamChars<'_> {
 impl LLSDStreamChars<'_> {
     /// Parse LLSD string expressed in notation format into an LLSDObject tree. No header.
     /// Strng form
-    pub fn parse(notation_str: &str) -> Result<LLSDValue, Error> {
-        let mut stream = LLSDStreamChars { cursor: notation_str.chars().peekable() };
-        match stream.parse_value() {
+    pub fn parse(notation_str: &str, options: &NotationOptions) -> Result<LLSDValue, Error> {
+        let mut stream = LLSDStreamChars { cursor: notation_str.chars().peekable(), options };
+        match stream.parse_value(0) {
             Ok(v) => Ok(v),
             Err(e) => {
                 //  Useful error message
@@ -351,12 +472,20 @@ impl LLSDStreamChars<'_> {
 struct LLSDStreamBytes<'a> {
     /// Stream is composed of peekable bytes.
     cursor: Peekable<std::slice::Iter<'a, u8>>,
+    /// Byte offset of the next byte, for error messages.
+    position: usize,
+    /// Parse options
+    options: &'a NotationOptions,
 }
 
 impl LLSDStream<u8, Peekable<Bytes<'_>>> for LLSDStreamBytes<'_> {
     /// Get next byte.
     fn next(&mut self) -> Option<u8> {
-        self.cursor.next().copied()
+        let b = self.cursor.next().copied();
+        if b.is_some() {
+            self.position += 1;
+        }
+        b
     }
     /// Peek at next byte.
     fn peek(&mut self) -> Option<&u8> {
@@ -366,6 +495,11 @@ impl LLSDStream<u8, Peekable<Bytes<'_>>> for LLSDStreamBytes<'_> {
     fn into_char(ch: &u8) -> char {
         (*ch).into()
     }
+
+    /// Parse options
+    fn options(&self) -> &NotationOptions {
+        self.options
+    }
     
     /// Parse binary value.
     /// Format is b16"value" or b64"value" or b(cnt)"value".
@@ -386,25 +520,7 @@ impl LLSDStream<u8, Peekable<Bytes<'_>>> for LLSDStreamBytes<'_> {
                     self.consume_char('"')?;     // count must be correct or this will fail.
                     Ok(LLSDValue::Binary(s))     // not sure about this
                 }                 
-                '1' => {
-                    self.consume_char('1')?;
-                    self.consume_char('6')?;          // base 16
-                    self.consume_char('"')?;          // begin quote
-                    let mut s = self.parse_quoted_string('"')?;
-                    s.retain(|c| !c.is_whitespace());
-                    Ok(LLSDValue::Binary(hex::decode(s)?))
-                }
-                '6' => {
-                    self.consume_char('6')?;
-                    self.consume_char('4')?;
-                    self.consume_char('"')?;          // begin quote
-                    let mut s = self.parse_quoted_string('"')?;
-                    s.retain(|c| !c.is_whitespace());
-                    println!("Base 64 decode input: \"{}\"", s);    // ***TEMP***
-                    let bytes = base64::engine::general_purpose::STANDARD.decode(s)?;
-                    Ok(LLSDValue::Binary(bytes))
-                }
-                _ => Err(anyhow!("Binary value started with {} instead of (, 1, or 6", ch))   
+                _ => self.parse_encoded_binary(),
             } 
         } else {
             Err(anyhow!("Binary value started with EOF"))   
@@ -418,18 +534,35 @@ impl LLSDStream<u8, Peekable<Bytes<'_>>> for LLSDStreamBytes<'_> {
         //  At this point, we are supposed to have a quoted string of ASCII characters.
         //  If this can be validy converted as UTF-8, it will be accepted.
         self.consume_char('"')?;
+        let start = self.position;
         let s = self.next_chunk(cnt)?;
-        self.consume_char('"')?;
-        Ok(LLSDValue::String(String::from_utf8(s)?))
+        //  Check UTF-8 before the closing quote. A wrong count usually
+        //  splits a character, and this says so more clearly than a missing quote.
+        match String::from_utf8(s) {
+            Ok(s) => {
+                self.consume_char('"')?;
+                Ok(LLSDValue::String(s))
+            }
+            Err(e) => {
+                let err = e.utf8_error();
+                Err(anyhow!(
+                    "s({}) string at byte offset {} is not valid UTF-8: {} at byte {} of the string. Check the count.",
+                    cnt,
+                    start,
+                    if err.error_len().is_none() { "multibyte character cut off" } else { "invalid byte sequence" },
+                    err.valid_up_to()
+                ))
+            }
+        }
     }
 }
 
 impl LLSDStreamBytes<'_> {
     /// Parse LLSD string expressed in notation format into an LLSDObject tree. No header.
     /// Bytes form.
-    pub fn parse(notation_bytes: &[u8]) -> Result<LLSDValue, Error> {
-        let mut stream = LLSDStreamBytes { cursor: notation_bytes.iter().peekable() };
-        stream.parse_value()
+    pub fn parse(notation_bytes: &[u8], options: &NotationOptions) -> Result<LLSDValue, Error> {
+        let mut stream = LLSDStreamBytes { cursor: notation_bytes.iter().peekable(), position: 0, options };
+        stream.parse_value(0)
     }
 
     /// Parse (NNN), which is used for length information.
@@ -460,7 +593,6 @@ impl LLSDStreamBytes<'_> {
 /// Extract the part of a string from the beginning to an iterator.
 fn beginning_to_iterator<'a>(orig: &'a str, pos: &Peekable<Chars>) -> &'a str {
     let suffix: String = pos.clone().collect();
-    println!("Suffix: {}", suffix);
     if let Some(s) = orig.strip_suffix(&suffix) {
         s
     } else {
@@ -472,7 +604,7 @@ fn beginning_to_iterator<'a>(orig: &'a str, pos: &Peekable<Chars>) -> &'a str {
 /// Unit tests
 fn notationparse1() {
     let s1 = "\"ABC☺DEF\"".to_string();  // string, including quotes, with emoji.
-    let mut stream1 = LLSDStreamChars { cursor: s1.chars().peekable() };
+    let mut stream1 = LLSDStreamChars { cursor: s1.chars().peekable(), options: &NotationOptions::default() };
     stream1.consume_char('"').unwrap(); // leading quote
     let v1 = stream1.parse_quoted_string('"').unwrap();
     assert_eq!(v1, "ABC☺DEF");
@@ -510,11 +642,24 @@ fn notationparse2() {
   }
 ]
 "#;
-    let parsed_s = LLSDStreamChars::parse(TESTNOTATION2);
+    let parsed_s = from_str(TESTNOTATION2);
     println!("Parse of string form {}: \n{:#?}", TESTNOTATION2, parsed_s);
-    let parsed_b = LLSDStreamBytes::parse(TESTNOTATION2.as_bytes());
+    let parsed_b = from_bytes(TESTNOTATION2.as_bytes());
     println!("Parse of byte form: {:#?}", parsed_b);
-    assert_eq!(parsed_s.unwrap(), parsed_b.unwrap());
+    let parsed = parsed_s.unwrap();
+    assert_eq!(parsed, parsed_b.unwrap());
+    //  Find a UUID anywhere in the tree.
+    let asset_id = Uuid::parse_str("5868dd20-c25a-47bd-8b4c-dedc99ef9479").unwrap();
+    let found = parsed.find(|v| v.as_uuid() == Some(&asset_id));
+    assert_eq!(found, Some(&LLSDValue::UUID(asset_id)));
+    assert!(parsed.find(|v| v.as_uuid() == Some(&Uuid::nil())).is_none());
+    //  Array elements come out in order. Map order is arbitrary.
+    let reals: Vec<&LLSDValue> = parsed.find_all(|v| v.as_real().is_some());
+    assert_eq!(reals.len(), 6);
+    let position = reals.iter().position(|v| **v == LLSDValue::Real(70.9247)).unwrap();
+    assert_eq!(reals[position + 1..position + 3], [&LLSDValue::Real(254.378), &LLSDValue::Real(38.7304)]);
+    assert_eq!(parsed.find_all(|v| v.as_uuid().is_some()).len(), 7);
+    assert_eq!(parsed.find(|v| v.as_array().is_some()), Some(&parsed));
 }
 
 #[test]
@@ -586,3 +731,197 @@ fn notationparse5() {
     println!("Parse of byte form: {:#?}", parsed_b);
     assert!(parsed_b.is_err());
 }
+
+#[test]
+fn notationparseundef1() {
+    //  Undefined as a map value, alone, first, and last.
+    for s in ["{'x':!}", "{'x':!,'y':i1}", "{'y':i1,'x':!}", "{ 'x' : ! , }", "[!,!]"] {
+        let parsed = from_str(s).unwrap();
+        let generated = crate::ser::notation::to_string(&parsed).unwrap();
+        let reparsed = crate::de::auto_from_str(&generated).unwrap();
+        assert_eq!(parsed, reparsed);
+    }
+    let parsed = from_str("{'x':!}").unwrap();
+    assert_eq!(parsed.as_map().unwrap().get("x"), Some(&LLSDValue::Undefined));
+    assert_eq!(crate::ser::notation::to_string(&parsed).unwrap(), format!("{}{{'x':!}}", LLSDNOTATIONPREFIX));
+}
+
+#[test]
+fn notationparselenientdate1() {
+    let lenient = NotationOptions { lenient_dates: true, ..Default::default() };
+    let expected = LLSDValue::Date(1138804193.0);
+    for s in ["d\"2006-02-01 14:29:53\"", "d'2006-02-01T14:29:53'", "d\"2006-02-01T14:29:53Z\""] {
+        assert_eq!(from_str_with_options(s, &lenient).unwrap(), expected);
+        assert_eq!(from_bytes_with_options(s.as_bytes(), &lenient).unwrap(), expected);
+    }
+    //  Strict is the default.
+    assert!(from_str("d\"2006-02-01 14:29:53\"").is_err());
+    assert!(from_str("d\"2006-02-01T14:29:53\"").is_err());
+    assert_eq!(from_str("d\"2006-02-01T14:29:53Z\"").unwrap(), expected);
+}
+
+#[test]
+fn notationparseuuid1() {
+    //  A UUID is exactly 36 chars, so the terminator after it must not be consumed.
+    let id = Uuid::parse_str("3c115e51-04f4-523c-9fa6-98aff1034730").unwrap();
+    let sole_map_value = LLSDValue::Map([("id".to_string(), LLSDValue::UUID(id))].into_iter().collect());
+    let last_in_array = LLSDValue::Array(vec![LLSDValue::Integer(1), LLSDValue::UUID(id)]);
+    let followed_by_comma = LLSDValue::Array(vec![LLSDValue::UUID(id), LLSDValue::UUID(Uuid::nil()), LLSDValue::Integer(2)]);
+    for val in [sole_map_value, last_in_array, followed_by_comma] {
+        let s = crate::ser::notation::to_string(&val).unwrap();
+        let s = &s[LLSDNOTATIONPREFIX.len()..];
+        assert_eq!(from_str(s).unwrap(), val);
+        assert_eq!(from_bytes(s.as_bytes()).unwrap(), val);
+    }
+    //  Compact hand-written forms.
+    assert_eq!(from_str("{'id':u3c115e51-04f4-523c-9fa6-98aff1034730}").unwrap().as_map().unwrap()["id"], LLSDValue::UUID(id));
+    assert_eq!(from_str("[u3c115e51-04f4-523c-9fa6-98aff1034730,i2]").unwrap().array_len(), Some(2));
+}
+
+#[test]
+fn notationparsebinarystr1() {
+    //  Encoded binary is allowed in the UTF-8 form, byte-counted is not.
+    assert_eq!(from_str("b64\"SGVsbG8gd29ybGQ=\"").unwrap(), LLSDValue::Binary(b"Hello world".to_vec()));
+    assert_eq!(from_str("[b16\"0fa1\"]").unwrap(), LLSDValue::Array(vec![LLSDValue::Binary(vec![0x0f, 0xa1])]));
+    assert!(from_str("b(5)\"Hello\"").is_err());
+    assert_eq!(from_bytes(b"b(5)\"Hello\"").unwrap(), LLSDValue::Binary(b"Hello".to_vec()));
+}
+
+#[test]
+fn notationparseheader1() {
+    //  Every top-level scalar, through the serializer, header and all.
+    let scalars = [
+        LLSDValue::Undefined,
+        LLSDValue::Boolean(true),
+        LLSDValue::Boolean(false),
+        LLSDValue::Integer(5),
+        LLSDValue::Integer(-5),
+        LLSDValue::Real(2.5),
+        LLSDValue::UUID(Uuid::parse_str("67153d5b-3659-afb4-8510-adda2c034649").unwrap()),
+        LLSDValue::String("i5".to_string()),
+        LLSDValue::Date(1138804193.0),
+        LLSDValue::URI("http://example.com".to_string()),
+        LLSDValue::Binary(vec![1, 2, 3]),
+    ];
+    for val in scalars {
+        let s = crate::ser::notation::to_string(&val).unwrap();
+        assert!(s.starts_with(LLSDNOTATIONPREFIX));
+        assert_eq!(from_str(&s).unwrap(), val, "from_str of {:?}", s);
+        assert_eq!(from_bytes(s.as_bytes()).unwrap(), val, "from_bytes of {:?}", s);
+    }
+    //  Header without newline, and with leading whitespace.
+    assert_eq!(from_str("<? llsd/notation ?>i5").unwrap(), LLSDValue::Integer(5));
+    assert_eq!(from_bytes(b"  <? llsd/notation ?>\ni5").unwrap(), LLSDValue::Integer(5));
+    //  No header.
+    assert_eq!(from_str("i5").unwrap(), LLSDValue::Integer(5));
+}
+
+#[test]
+fn notationparseduplicatekey1() {
+    const TESTNOTATION: &str = "{'a':i1,'b':i2,'a':i3}";
+    //  Default is last wins.
+    let val = from_str(TESTNOTATION).unwrap();
+    assert_eq!(val, from_str("{'a':i3,'b':i2}").unwrap());
+    assert_eq!(from_bytes(TESTNOTATION.as_bytes()).unwrap(), val);
+    //  Strict.
+    let options = NotationOptions { error_on_duplicate_keys: true, ..Default::default() };
+    assert!(from_str_with_options(TESTNOTATION, &options).is_err());
+    assert!(from_bytes_with_options(TESTNOTATION.as_bytes(), &options).is_err());
+    assert!(from_str_with_options("{'a':i1,'b':i2}", &options).is_ok());
+}
+
+#[test]
+fn notationparsefractionaldate1() {
+    let parsed = from_str("d\"2006-02-01T14:29:53.123Z\"").unwrap();
+    assert!((parsed.as_date().unwrap() - 1138804193.123).abs() < 1.0e-6);
+    let s = crate::ser::notation::to_string(&parsed).unwrap();
+    assert_eq!(s, format!("{}d\"2006-02-01T14:29:53.123Z\"", LLSDNOTATIONPREFIX));
+    assert_eq!(from_str(&s).unwrap(), parsed);
+}
+
+#[test]
+fn notationparsesizedstringutf8error1() {
+    //  "\u{e9}" is 2 bytes in UTF-8, so "a\u{e9}" is 3 bytes. A count of 2 splits the character.
+    let err = from_bytes("['ab',s(2)\"a\u{e9}\"]".as_bytes()).unwrap_err().to_string();
+    assert!(err.contains("s(2)"), "{}", err);
+    assert!(err.contains("byte offset 11"), "{}", err);
+    assert!(err.contains("cut off at byte 1"), "{}", err);
+    //  Invalid bytes, as opposed to a split character.
+    let err = from_bytes(b"s(2)\"\xff\xfe\"").unwrap_err().to_string();
+    assert!(err.contains("invalid byte sequence at byte 0"), "{}", err);
+    //  The right count works.
+    assert_eq!(from_bytes("s(3)\"a\u{e9}\"".as_bytes()).unwrap(), LLSDValue::String("a\u{e9}".to_string()));
+}
+
+#[test]
+fn notationparsefloatsuffix1() {
+    let s = "[r1.5f,r-2F, r3]";
+    let expected = LLSDValue::Array(vec![LLSDValue::Real(1.5), LLSDValue::Real(-2.0), LLSDValue::Real(3.0)]);
+    let options = NotationOptions { accept_float_suffix: true, ..Default::default() };
+    assert_eq!(from_str_with_options(s, &options).unwrap(), expected);
+    assert_eq!(from_bytes_with_options(s.as_bytes(), &options).unwrap(), expected);
+    assert_eq!(from_str_with_options("r1.5f", &options).unwrap(), LLSDValue::Real(1.5));
+    //  Rejected by default.
+    assert!(from_str(s).is_err());
+    assert!(from_bytes(s.as_bytes()).is_err());
+}
+
+#[test]
+fn notationparsedepthtest1() {
+    use crate::de::DEFAULT_MAX_DEPTH;
+    fn nested(n: usize) -> String {
+        "[".repeat(n) + &"]".repeat(n)
+    }
+    assert!(from_str(&nested(DEFAULT_MAX_DEPTH)).is_ok());
+    assert!(from_bytes(nested(DEFAULT_MAX_DEPTH).as_bytes()).is_ok());
+    let err = from_str(&nested(DEFAULT_MAX_DEPTH + 1)).unwrap_err();
+    assert!(err.to_string().contains("maximum nesting depth exceeded"));
+    //  Hostile input is an error, not a stack overflow.
+    let hostile = "[".repeat(1_000_000);
+    assert!(from_str(&hostile).is_err());
+    assert!(from_bytes(hostile.as_bytes()).is_err());
+    //  Maps count too.
+    let options = NotationOptions { max_depth: 2, ..Default::default() };
+    assert!(from_str_with_options("{'a':[i1]}", &options).is_ok());
+    assert!(from_str_with_options("{'a':[{}]}", &options).is_err());
+}
+
+#[test]
+fn notationparseexponenttest1() {
+    assert_eq!(from_str("r6.022e23").unwrap(), LLSDValue::Real(6.022e23));
+    assert_eq!(from_str("r-1.5E-10").unwrap(), LLSDValue::Real(-1.5E-10));
+    assert_eq!(from_str("r1e+3").unwrap(), LLSDValue::Real(1000.0));
+    let s = "[r6.022e23,r-1.5E-10, i3]";
+    let expected = LLSDValue::Array(vec![LLSDValue::Real(6.022e23), LLSDValue::Real(-1.5E-10), LLSDValue::Integer(3)]);
+    assert_eq!(from_str(s).unwrap(), expected);
+    assert_eq!(from_bytes(s.as_bytes()).unwrap(), expected);
+    assert!(from_str("r1e").is_err());
+    assert!(from_str("r1e5e5").is_err());
+}
+
+#[test]
+fn notationparsenonfinitetest1() {
+    assert_eq!(from_str("rinf").unwrap(), LLSDValue::Real(f64::INFINITY));
+    assert_eq!(from_str("r-Infinity").unwrap(), LLSDValue::Real(f64::NEG_INFINITY));
+    assert_eq!(from_bytes(b"r+INF").unwrap(), LLSDValue::Real(f64::INFINITY));
+    assert!(from_str("rnan").unwrap().as_real().unwrap().is_nan());
+    assert!(from_bytes(b"rNaN").unwrap().as_real().unwrap().is_nan());
+    assert!(from_str("rinfinite").is_err());
+    assert!(from_str("rnope").is_err());
+}
+
+#[test]
+fn notationparsewhitespacetest1() {
+    let s = "{\r\n\t'a':\ti1,\r\n\t'b' :\t[\tr2.5 ,\t'x'\t]\r\n}\r\n";
+    let expected = LLSDValue::Map(
+        [
+            ("a".to_string(), LLSDValue::Integer(1)),
+            ("b".to_string(), LLSDValue::Array(vec![LLSDValue::Real(2.5), LLSDValue::String("x".to_string())])),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    assert_eq!(from_str(s).unwrap(), expected);
+    assert_eq!(from_bytes(s.as_bytes()).unwrap(), expected);
+}
+
@@ -0,0 +1,366 @@
+//! # de/serde_impl -- de-serialize an LLSDValue tree into Rust data, using Serde.
+//!
+//!  Library for serializing and de-serializing data in
+//!  Linden Lab Structured Data format.
+//!
+//!  Format documentation is at http://wiki.secondlife.com/wiki/LLSD
+//!
+//!  Parse LLSD in any format into an LLSDValue, then convert that
+//!  into any type implementing Deserialize.
+//!
+//!  Map becomes a struct or map, Array a sequence or tuple, Integer any
+//!  integer type it fits in, Real f32 or f64, UUID a [u8;16] or a String,
+//!  Binary a Vec<u8>. Undefined, or a missing map key, is None for an Option.
+//!  Enums are externally tagged, as ser::serde_impl writes them.
+//!  Errors give the path to the bad value, such as "/points/0/x".
+//
+//  Animats
+//  October, 2026.
+//  License: LGPL.
+//
+use crate::{LLSDMap, LLSDValue};
+use serde::de::value::{BorrowedStrDeserializer, SeqDeserializer as BytesDeserializer};
+use serde::de::{self, Deserialize, DeserializeSeed, IntoDeserializer, Unexpected, Visitor};
+use std::fmt;
+
+/// Convert an LLSDValue tree into anything deserializable.
+/// Strings can be borrowed from the tree.
+pub fn from_value<'a, T: Deserialize<'a>>(val: &'a LLSDValue) -> Result<T, anyhow::Error> {
+    Ok(T::deserialize(ValueDeserializer(val))?)
+}
+
+/// Serde deserialization error, with the path to the value where it happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    msg: String,
+    /// Path components, innermost first.
+    path: Vec<String>,
+}
+
+impl Error {
+    /// Add the enclosing map key or array index to the path.
+    fn within(mut self, component: impl ToString) -> Self {
+        self.path.push(component.to_string());
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            f.write_str(&self.msg)
+        } else {
+            let path: Vec<String> = self.path.iter().rev().cloned().collect();
+            write!(f, "{} at {}", self.msg, crate::tokens_to_pointer(&path))
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error { msg: msg.to_string(), path: Vec::new() }
+    }
+}
+
+/// What a value is, for type mismatch errors.
+fn unexpected(val: &LLSDValue) -> Unexpected<'_> {
+    match val {
+        LLSDValue::Undefined => Unexpected::Unit,
+        LLSDValue::Boolean(v) => Unexpected::Bool(*v),
+        LLSDValue::Real(v) => Unexpected::Float(*v),
+        LLSDValue::Integer(v) => Unexpected::Signed((*v).into()),
+        LLSDValue::UUID(_) => Unexpected::Other("uuid"),
+        LLSDValue::String(v) => Unexpected::Str(v),
+        LLSDValue::Date(_) => Unexpected::Other("date"),
+        LLSDValue::URI(_) => Unexpected::Other("uri"),
+        LLSDValue::Binary(v) => Unexpected::Bytes(v),
+        LLSDValue::Map(_) => Unexpected::Map,
+        LLSDValue::Array(_) => Unexpected::Seq,
+    }
+}
+
+/// Deserializer over one node of an LLSDValue tree.
+struct ValueDeserializer<'de>(&'de LLSDValue);
+
+impl ValueDeserializer<'_> {
+    fn invalid_type(&self, exp: &dyn de::Expected) -> Error {
+        de::Error::invalid_type(unexpected(self.0), exp)
+    }
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            LLSDValue::Undefined => visitor.visit_unit(),
+            LLSDValue::Boolean(v) => visitor.visit_bool(*v),
+            LLSDValue::Real(v) => visitor.visit_f64(*v),
+            LLSDValue::Integer(v) => visitor.visit_i32(*v),
+            LLSDValue::UUID(v) => visitor.visit_string(v.to_string()),
+            LLSDValue::String(v) | LLSDValue::URI(v) => visitor.visit_borrowed_str(v),
+            LLSDValue::Date(v) => visitor.visit_f64(*v),
+            LLSDValue::Binary(v) => visitor.visit_borrowed_bytes(v),
+            LLSDValue::Map(_) => self.deserialize_map(visitor),
+            LLSDValue::Array(_) => self.deserialize_seq(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            LLSDValue::Undefined => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            LLSDValue::Undefined => visitor.visit_unit(),
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            LLSDValue::Array(v) => visitor.visit_seq(SeqAccess { iter: v.iter().enumerate() }),
+            //  Vec<u8> arrives here.
+            LLSDValue::Binary(v) => visitor.visit_seq(BytesDeserializer::new(v.iter().copied())),
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            //  [u8; 16] arrives here.
+            LLSDValue::UUID(v) if len == 16 => visitor.visit_seq(BytesDeserializer::new(v.as_bytes().iter().copied())),
+            _ => self.deserialize_seq(visitor),
+        }
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            LLSDValue::Map(v) => visitor.visit_map(MapAccess { iter: v.iter(), value: None }),
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            LLSDValue::Binary(v) => visitor.visit_borrowed_bytes(v),
+            LLSDValue::UUID(v) => visitor.visit_bytes(v.as_bytes()),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.0 {
+            //  Unit variant, as its name.
+            LLSDValue::String(v) => visitor.visit_enum(v.as_str().into_deserializer()),
+            //  Other variants, as a map with one entry.
+            LLSDValue::Map(v) if v.len() == 1 => {
+                let (variant, value) = v.iter().next().expect("one entry");
+                visitor.visit_enum(EnumAccess { variant, value })
+            }
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string identifier
+    }
+}
+
+/// Array elements, with their indices for error paths.
+struct SeqAccess<'de> {
+    iter: std::iter::Enumerate<std::slice::Iter<'de, LLSDValue>>,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess<'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Error> {
+        match self.iter.next() {
+            Some((n, val)) => seed.deserialize(ValueDeserializer(val)).map(Some).map_err(|e| e.within(n)),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+/// Map entries, with keys for error paths.
+struct MapAccess<'de> {
+    iter: <&'de LLSDMap as IntoIterator>::IntoIter,
+    /// Entry whose key was just returned
+    value: Option<(&'de String, &'de LLSDValue)>,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Error> {
+        match self.iter.next() {
+            Some((key, val)) => {
+                self.value = Some((key, val));
+                seed.deserialize(BorrowedStrDeserializer::new(key)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let (key, val) = self.value.take().ok_or_else(|| de::Error::custom("Map value without a key"))?;
+        seed.deserialize(ValueDeserializer(val)).map_err(|e| e.within(key))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+/// Enum variant, as a one-entry map.
+struct EnumAccess<'de> {
+    variant: &'de String,
+    value: &'de LLSDValue,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess<'de> {
+    type Error = Error;
+    type Variant = VariantAccess<'de>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, VariantAccess<'de>), Error> {
+        let variant = seed.deserialize(BorrowedStrDeserializer::<Error>::new(self.variant))?;
+        Ok((variant, VariantAccess { variant: self.variant, value: self.value }))
+    }
+}
+
+/// Content of an enum variant.
+struct VariantAccess<'de> {
+    variant: &'de String,
+    value: &'de LLSDValue,
+}
+
+impl<'de> de::VariantAccess<'de> for VariantAccess<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        de::Deserialize::deserialize(ValueDeserializer(self.value)).map_err(|e: Error| e.within(self.variant))
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(ValueDeserializer(self.value)).map_err(|e| e.within(self.variant))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_seq(ValueDeserializer(self.value), visitor).map_err(|e| e.within(self.variant))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_map(ValueDeserializer(self.value), visitor).map_err(|e| e.within(self.variant))
+    }
+}
+
+#[test]
+fn serdedetest1() {
+    use serde::{Deserialize, Serialize};
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct NamedPoint {
+        name: String,
+        x: f64,
+        y: f32,
+        id: u8,
+        tags: Vec<String>,
+        note: Option<String>,
+        shape: Shape,
+    }
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Circle(f64),
+        Rect { w: i32, h: i32 },
+        Square,
+    }
+    let llsd = crate::notation_from_str(
+        "{'name':'origin','x':r1.5,'y':i-2,'id':i7,'tags':['a','b'],'shape':{'Rect':{'w':i2,'h':i3}}}",
+    )
+    .unwrap();
+    let pt: NamedPoint = from_value(&llsd).unwrap();
+    assert_eq!(
+        pt,
+        NamedPoint {
+            name: "origin".to_string(),
+            x: 1.5,
+            y: -2.0,
+            id: 7,
+            tags: vec!["a".to_string(), "b".to_string()],
+            note: None, // missing key
+            shape: Shape::Rect { w: 2, h: 3 },
+        }
+    );
+    //  Round trip through the serializer.
+    let val = crate::to_value(&pt).unwrap();
+    assert_eq!(from_value::<NamedPoint>(&val).unwrap(), pt);
+    assert_eq!(from_value::<Shape>(&LLSDValue::String("Square".to_string())).unwrap(), Shape::Square);
+    assert_eq!(from_value::<Shape>(&crate::to_value(&Shape::Circle(1.0)).unwrap()).unwrap(), Shape::Circle(1.0));
+    //  Type mismatch errors give the path.
+    let bad = crate::notation_from_str("{'points':[{'x':r1},{'x':'oops'}]}").unwrap();
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct Points {
+        points: Vec<Pt>,
+    }
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct Pt {
+        x: f64,
+    }
+    let err = from_value::<Points>(&bad).unwrap_err().to_string();
+    assert!(err.contains("invalid type: string \"oops\", expected f64 at /points/1/x"), "{}", err);
+    //  Integers must fit.
+    assert!(from_value::<u8>(&LLSDValue::Integer(300)).is_err());
+    assert!(from_value::<u32>(&LLSDValue::Integer(-1)).is_err());
+    //  UUID as bytes or string, Binary as Vec<u8>, borrowed strings.
+    let id = uuid::Uuid::parse_str("67153d5b-3659-afb4-8510-adda2c034649").unwrap();
+    assert_eq!(from_value::<[u8; 16]>(&LLSDValue::UUID(id)).unwrap(), *id.as_bytes());
+    assert_eq!(from_value::<String>(&LLSDValue::UUID(id)).unwrap(), id.to_string());
+    assert_eq!(from_value::<Vec<u8>>(&LLSDValue::Binary(vec![1, 2, 3])).unwrap(), vec![1, 2, 3]);
+    let s = LLSDValue::String("borrowed".to_string());
+    assert_eq!(from_value::<&str>(&s).unwrap(), "borrowed");
+}
@@ -12,7 +12,7 @@
 //  February, 2021.
 //  License: LGPL.
 //
-use crate::LLSDValue;
+use crate::{LLSDMap, LLSDValue};
 use anyhow::{anyhow, Error};
 use ascii85;
 use base64;
@@ -20,22 +20,64 @@ use base64::Engine;
 use quick_xml::events::attributes::Attributes;
 use quick_xml::events::Event;
 use quick_xml::Reader;
-use std::collections::HashMap;
 use std::io::{BufRead, BufReader};
-////use uuid;
 //
 //  Constants
 //
 pub const LLSDXMLPREFIX: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<llsd>\n";
 pub const LLSDXMLSENTINEL: &str = "<?xml"; // Must begin with this.
+/// How far to look for the start of the XML when skipping leading junk.
+pub const LLSDXMLJUNKLIMIT: usize = 256;
+/// Options for parsing XML LLSD.
+#[derive(Debug, Clone)]
+pub struct XmlOptions {
+    /// Accept dates with a space instead of 'T', and with no
+    /// time zone offset, which is taken as UTC. SL sometimes emits these.
+    pub lenient_dates: bool,
+    /// Skip stray bytes before the first "<?xml" or "<llsd", such as
+    /// junk at the start of an HTTP body. Looks only at the first
+    /// LLSDXMLJUNKLIMIT bytes. Applies to from_str_with_options.
+    pub skip_leading_junk: bool,
+    /// Maximum nesting of maps and arrays.
+    pub max_depth: usize,
+}
+
+impl Default for XmlOptions {
+    fn default() -> Self {
+        XmlOptions { lenient_dates: false, skip_leading_junk: false, max_depth: crate::de::DEFAULT_MAX_DEPTH }
+    }
+}
+
 ///    Parse LLSD expressed in XML into an LLSD tree.
 pub fn from_str(xmlstr: &str) -> Result<LLSDValue, Error> {
-    from_reader(&mut BufReader::new(xmlstr.as_bytes()))
+    from_str_with_options(xmlstr, &XmlOptions::default())
+}
+
+///    Parse LLSD expressed in XML into an LLSD tree, with options.
+pub fn from_str_with_options(xmlstr: &str, options: &XmlOptions) -> Result<LLSDValue, Error> {
+    let xmlstr = if options.skip_leading_junk {
+        &xmlstr[find_xml_start(xmlstr)?..]
+    } else {
+        xmlstr
+    };
+    from_reader_with_options(&mut BufReader::new(xmlstr.as_bytes()), options)
+}
+
+/// Find the start of the XML, "<?xml" or "<llsd", within LLSDXMLJUNKLIMIT bytes.
+fn find_xml_start(xmlstr: &str) -> Result<usize, Error> {
+    let head = &xmlstr.as_bytes()[..xmlstr.len().min(LLSDXMLJUNKLIMIT + LLSDXMLSENTINEL.len())];
+    head.windows(LLSDXMLSENTINEL.len())
+        .position(|w| w == LLSDXMLSENTINEL.as_bytes() || w == b"<llsd")
+        .ok_or_else(|| anyhow!("No <?xml or <llsd found in the first {} bytes", LLSDXMLJUNKLIMIT))
 }
-////let mut reader = Reader::from_str(xmlstr);
 
 /// Read XML from buffered source and parse into LLSDValue.
 pub fn from_reader<R: BufRead>(rdr: &mut R) -> Result<LLSDValue, Error> {
+    from_reader_with_options(rdr, &XmlOptions::default())
+}
+
+/// Read XML from buffered source and parse into LLSDValue, with options.
+pub fn from_reader_with_options<R: BufRead>(rdr: &mut R, options: &XmlOptions) -> Result<LLSDValue, Error> {
     let mut reader = Reader::from_reader(rdr); // create an XML reader from a sequential reader
     reader.trim_text(true); // do not want trailing blanks
     reader.expand_empty_elements(true); // want end tag events always
@@ -45,7 +87,7 @@ pub fn from_reader<R: BufRead>(rdr: &mut R) -> Result<LLSDValue, Error> {
     loop {
         match reader.read_event(&mut buf) {
             Ok(Event::Start(ref e)) => {
-                match e.name() {
+                match e.local_name() {
                     b"llsd" => {
                         if output.is_some() {
                             return Err(anyhow!("More than one <llsd> block in data"));
@@ -53,14 +95,14 @@ pub fn from_reader<R: BufRead>(rdr: &mut R) -> Result<LLSDValue, Error> {
                         let mut buf2 = Vec::new();
                         match reader.read_event(&mut buf2) {
                             Ok(Event::Start(ref e)) => {
-                                let tagname = std::str::from_utf8(e.name())?; // tag name as string to start parse
-                                                                              //  This does all the real work.
-                                output = Some(parse_value(&mut reader, tagname, &e.attributes())?);
+                                let tagname = std::str::from_utf8(e.local_name())?; // tag name as string to start parse
+                                                                                    //  This does all the real work.
+                                output = Some(parse_value(&mut reader, tagname, &e.attributes(), options, 0)?);
                             }
                             _ => {
                                 return Err(anyhow!(
                                     "Expected LLSD data, found {:?} error at position {}",
-                                    e.name(),
+                                    e.local_name(),
                                     reader.buffer_position()
                                 ))
                             }
@@ -69,7 +111,7 @@ pub fn from_reader<R: BufRead>(rdr: &mut R) -> Result<LLSDValue, Error> {
                     _ => {
                         return Err(anyhow!(
                             "Expected <llsd>, found {:?} error at position {}",
-                            e.name(),
+                            e.local_name(),
                             reader.buffer_position()
                         ))
                     }
@@ -99,18 +141,26 @@ pub fn from_reader<R: BufRead>(rdr: &mut R) -> Result<LLSDValue, Error> {
 }
 
 /// Parse one value - real, integer, map, etc. Recursive.
-////fn parse_value<R: Read+BufRead>(rdr: &mut R) -> Result<LLSDValue, Error> {
+/// Depth is the number of enclosing maps and arrays.
 fn parse_value<R: BufRead>(
     reader: &mut Reader<&mut R>,
     starttag: &str,
     attrs: &Attributes,
+    options: &XmlOptions,
+    depth: usize,
 ) -> Result<LLSDValue, Error> {
     //  Entered with a start tag alread parsed and in starttag
     match starttag {
         "undef" | "real" | "integer" | "boolean" | "string" | "uri" | "binary" | "uuid"
-        | "date" => parse_primitive_value(reader, starttag, attrs),
-        "map" => parse_map(reader),
-        "array" => parse_array(reader),
+        | "date" => parse_primitive_value(reader, starttag, attrs, options),
+        "map" => {
+            crate::de::check_depth(depth, options.max_depth)?;
+            parse_map(reader, options, depth + 1)
+        }
+        "array" => {
+            crate::de::check_depth(depth, options.max_depth)?;
+            parse_array(reader, options, depth + 1)
+        }
         _ => Err(anyhow!(
             "Unknown data type <{}> at position {}",
             starttag,
@@ -124,16 +174,19 @@ fn parse_primitive_value<R: BufRead>(
     reader: &mut Reader<&mut R>,
     starttag: &str,
     attrs: &Attributes,
+    options: &XmlOptions,
 ) -> Result<LLSDValue, Error> {
     //  Entered with a start tag already parsed and in starttag
     let mut texts = Vec::new(); // accumulate text here
     let mut buf = Vec::new();
     loop {
+        buf.clear(); // previous event no longer needed, so buffer only holds one event
         let event = reader.read_event(&mut buf);
         match event {
             Ok(Event::Text(e)) => texts.push(e.unescape_and_decode(reader)?),
+            Ok(Event::CData(e)) => texts.push(std::str::from_utf8(&e)?.to_string()), // literal, no unescape
             Ok(Event::End(ref e)) => {
-                let tagname = std::str::from_utf8(e.name())?; // tag name as string
+                let tagname = std::str::from_utf8(e.local_name())?; // tag name as string
                 if starttag != tagname {
                     return Err(anyhow!(
                         "Unmatched XML tags: <{}> .. <{}>",
@@ -158,13 +211,13 @@ fn parse_primitive_value<R: BufRead>(
                     "integer" => Ok(LLSDValue::Integer(parse_integer(&text)?)),
                     "boolean" => Ok(LLSDValue::Boolean(parse_boolean(&text)?)),
                     "string" => Ok(LLSDValue::String(text)),
-                    "uri" => Ok(LLSDValue::String(text)),
+                    "uri" => Ok(LLSDValue::URI(text)),
                     "uuid" => Ok(LLSDValue::UUID(if text.is_empty() {
                         uuid::Uuid::nil()
                     } else {
                         uuid::Uuid::parse_str(&text)?
                     })),
-                    "date" => Ok(LLSDValue::Date(parse_date(&text)?)),
+                    "date" => Ok(LLSDValue::Date(parse_date(&text, options.lenient_dates)?)),
                     "binary" => Ok(LLSDValue::Binary(parse_binary(&text, attrs)?)),
                     _ => Err(anyhow!(
                         "Unexpected primitive data type <{}> at position {}",
@@ -201,19 +254,20 @@ fn parse_primitive_value<R: BufRead>(
 }
 
 //  Parse one map.
-fn parse_map<R: BufRead>(reader: &mut Reader<&mut R>) -> Result<LLSDValue, Error> {
+fn parse_map<R: BufRead>(reader: &mut Reader<&mut R>, options: &XmlOptions, depth: usize) -> Result<LLSDValue, Error> {
     //  Entered with a "map" start tag just parsed.
-    let mut map: HashMap<String, LLSDValue> = HashMap::new(); // accumulating map
+    let mut map = LLSDMap::new(); // accumulating map
     let mut texts = Vec::new(); // accumulate text here
     let mut buf = Vec::new();
     loop {
+        buf.clear(); // previous event no longer needed, so buffer only holds one event
         let event = reader.read_event(&mut buf);
         match event {
             Ok(Event::Start(ref e)) => {
-                let tagname = std::str::from_utf8(e.name())?; // tag name as string
+                let tagname = std::str::from_utf8(e.local_name())?; // tag name as string
                 match tagname {
                     "key" => {
-                        let (k, v) = parse_map_entry(reader)?; // read one key/value pair
+                        let (k, v) = parse_map_entry(reader, options, depth)?; // read one key/value pair
                         let _dup = map.insert(k, v); // insert into map
                                                      //  Duplicates are not errors, per LLSD spec.
                     }
@@ -225,7 +279,7 @@ fn parse_map<R: BufRead>(reader: &mut Reader<&mut R>) -> Result<LLSDValue, Error
             Ok(Event::Text(e)) => texts.push(e.unescape_and_decode(reader)?),
             Ok(Event::End(ref e)) => {
                 //  End of an XML tag. No text expected.
-                let tagname = std::str::from_utf8(e.name())?; // tag name as string
+                let tagname = std::str::from_utf8(e.local_name())?; // tag name as string
                 if "map" != tagname {
                     return Err(anyhow!("Unmatched XML tags: <{}> .. <{}>", "map", tagname));
                 };
@@ -258,21 +312,27 @@ fn parse_map<R: BufRead>(reader: &mut Reader<&mut R>) -> Result<LLSDValue, Error
 
 //  Parse one map entry.
 //  Format <key> STRING </key> LLSDVALUE
-fn parse_map_entry<R: BufRead>(reader: &mut Reader<&mut R>) -> Result<(String, LLSDValue), Error> {
+fn parse_map_entry<R: BufRead>(
+    reader: &mut Reader<&mut R>,
+    options: &XmlOptions,
+    depth: usize,
+) -> Result<(String, LLSDValue), Error> {
     //  Entered with a "key" start tag just parsed.  Expecting text.
     let mut texts = Vec::new(); // accumulate text here
     let mut buf = Vec::new();
     loop {
+        buf.clear(); // previous event no longer needed, so buffer only holds one event
         let event = reader.read_event(&mut buf);
         match event {
             Ok(Event::Start(ref e)) => {
-                let tagname = std::str::from_utf8(e.name())?; // tag name as string
+                let tagname = std::str::from_utf8(e.local_name())?; // tag name as string
                 return Err(anyhow!("Expected 'key' in map, found '{}'", tagname));
             }
             Ok(Event::Text(e)) => texts.push(e.unescape_and_decode(reader)?),
+            Ok(Event::CData(e)) => texts.push(std::str::from_utf8(&e)?.to_string()), // literal, no unescape
             Ok(Event::End(ref e)) => {
                 //  End of an XML tag. Should be </key>
-                let tagname = std::str::from_utf8(e.name())?; // tag name as string
+                let tagname = std::str::from_utf8(e.local_name())?; // tag name as string
                 if "key" != tagname {
                     return Err(anyhow!("Unmatched XML tags: <{}> .. <{}>", "key", tagname));
                 };
@@ -281,8 +341,8 @@ fn parse_map_entry<R: BufRead>(reader: &mut Reader<&mut R>) -> Result<(String, L
                 texts.clear();
                 match reader.read_event(&mut buf) {
                     Ok(Event::Start(ref e)) => {
-                        let tagname = std::str::from_utf8(e.name())?; // tag name as string
-                        let v = parse_value(reader, tagname, &e.attributes())?; // parse next value
+                        let tagname = std::str::from_utf8(e.local_name())?; // tag name as string
+                        let v = parse_value(reader, tagname, &e.attributes(), options, depth)?; // parse next value
                         return Ok((k, v)); // return key value pair
                     }
                     _ => {
@@ -319,23 +379,24 @@ fn parse_map_entry<R: BufRead>(reader: &mut Reader<&mut R>) -> Result<(String, L
 }
 
 /// Parse one LLSD object. Recursive.
-fn parse_array<R: BufRead>(reader: &mut Reader<&mut R>) -> Result<LLSDValue, Error> {
+fn parse_array<R: BufRead>(reader: &mut Reader<&mut R>, options: &XmlOptions, depth: usize) -> Result<LLSDValue, Error> {
     //  Entered with an <array> tag just parsed.
     let mut texts = Vec::new(); // accumulate text here
     let mut buf = Vec::new();
     let mut items: Vec<LLSDValue> = Vec::new(); // accumulate items.
     loop {
+        buf.clear(); // previous event no longer needed, so buffer only holds one event
         let event = reader.read_event(&mut buf);
         match event {
             Ok(Event::Start(ref e)) => {
-                let tagname = std::str::from_utf8(e.name())?; // tag name as string
+                let tagname = std::str::from_utf8(e.local_name())?; // tag name as string
                                                               //  Parse one data item.
-                items.push(parse_value(reader, tagname, &e.attributes())?);
+                items.push(parse_value(reader, tagname, &e.attributes(), options, depth)?);
             }
             Ok(Event::Text(e)) => texts.push(e.unescape_and_decode(reader)?),
             Ok(Event::End(ref e)) => {
                 //  End of an XML tag. Should be </array>
-                let tagname = std::str::from_utf8(e.name())?; // tag name as string
+                let tagname = std::str::from_utf8(e.local_name())?; // tag name as string
                 if "array" != tagname {
                     return Err(anyhow!(
                         "Unmatched XML tags: <{}> .. <{}>",
@@ -379,8 +440,12 @@ fn parse_binary(s: &str, attrs: &Attributes) -> Result<Vec<u8>, Error> {
         Some(enc) => enc,
         None => "base64".to_string(), // default
     };
+    //  Long binary values may be wrapped onto multiple lines.
+    let mut s = s.to_string();
+    s.retain(|c| !c.is_ascii_whitespace());
+    let s = s.as_str();
     //  Decode appropriately.
-    Ok(match encoding.as_str() {
+    let bytes = match encoding.as_str() {
         "base64" => base64::engine::general_purpose::STANDARD.decode(s)?,
         "base16" => hex::decode(s)?,
         "base85" => match ascii85::decode(s) {
@@ -393,12 +458,35 @@ fn parse_binary(s: &str, attrs: &Attributes) -> Result<Vec<u8>, Error> {
                 encoding
             ))
         }
-    })
+    };
+    //  Some tools add a "len" attribute. If present, it must match, which catches truncation.
+    if let Some(len) = get_attr(attrs, b"len")? {
+        let len = len
+            .trim()
+            .parse::<usize>()
+            .map_err(|e| anyhow!("Bad <binary len=\"{}\">: {}", len, e))?;
+        if len != bytes.len() {
+            return Err(anyhow!(
+                "<binary len=\"{}\"> but decoded data is {} bytes long",
+                len,
+                bytes.len()
+            ));
+        }
+    }
+    Ok(bytes)
 }
 
 /// Parse ISO 9660 date, simple form.
-fn parse_date(s: &str) -> Result<i64, Error> {
-    Ok(chrono::DateTime::parse_from_rfc3339(s)?.timestamp())
+/// Some non-LL producers put an integer UNIX epoch in seconds inside <date>,
+/// so if RFC 3339 parsing fails, try that.
+fn parse_date(s: &str, lenient: bool) -> Result<f64, Error> {
+    match crate::de::parse_rfc3339_date(s, lenient) {
+        Ok(secs) => Ok(secs),
+        Err(e) => match s.parse::<i64>() {
+            Ok(secs) => Ok(secs as f64), // bare epoch integer
+            Err(_) => Err(e),           // report the RFC 3339 error, the primary form
+        },
+    }
 }
 
 /// Parse integer. LSL allows the empty string as 0.
@@ -412,9 +500,10 @@ fn parse_integer(s: &str) -> Result<i32, Error> {
 }
 
 ///  Parse boolean. LSL allows 0. 0.0, false, 1. 1.0, true.
+///  The empty string is false, like an empty <integer /> is 0.
 fn parse_boolean(s: &str) -> Result<bool, Error> {
     Ok(match s {
-        "0" | "0.0" => false,
+        "" | "0" | "0.0" => false,
         "1" | "1.0" => true,
         _ => s.parse::<bool>()?,
     })
@@ -453,6 +542,16 @@ fn xmlparsetest1() {
 
     const TESTXMLZEROARRAY: [i32;3] = [ 0, 100, 0 ]; // expected values
 
+    const TESTXMLEMPTYBOOLEAN: &str = r#"
+<?xml version="1.0" encoding="UTF-8"?>
+<llsd>
+<array>
+<boolean>true</boolean>
+<boolean />
+</array>
+</llsd>
+"#;
+
     const TESTXMLNAN: &str = r#"
 <?xml version="1.0" encoding="UTF-8"?>
 <llsd>
@@ -535,6 +634,10 @@ fn xmlparsetest1() {
             assert_eq!(n, *(item.as_integer().unwrap()));  // must match
         }
     }
+    //  Test empty boolean case, where an empty <boolean /> is false.
+    {   let parsed0 = from_str(TESTXMLEMPTYBOOLEAN).unwrap();
+        assert_eq!(parsed0, LLSDValue::Array(vec![LLSDValue::Boolean(true), LLSDValue::Boolean(false)]));
+    }
     //  Test NAN case
     {
         let parsed1 = from_str(TESTXMLNAN).unwrap();
@@ -549,3 +652,228 @@ fn xmlparsetest1() {
 
     
 }
+
+#[test]
+fn xmlparsedatetest1() {
+    //  RFC 3339 date, the standard form.
+    const TESTXMLDATE: &str = r#"
+<?xml version="1.0" encoding="UTF-8"?>
+<llsd>
+<date>2006-02-01T14:29:53Z</date>
+</llsd>
+"#;
+    //  Bare UNIX epoch integer, as some non-LL producers emit.
+    const TESTXMLEPOCH: &str = r#"
+<?xml version="1.0" encoding="UTF-8"?>
+<llsd>
+<date>1138804193</date>
+</llsd>
+"#;
+    let parsed_date = from_str(TESTXMLDATE).unwrap();
+    assert_eq!(parsed_date, LLSDValue::Date(1138804193.0));
+    let parsed_epoch = from_str(TESTXMLEPOCH).unwrap();
+    assert_eq!(parsed_epoch, parsed_date);   // both forms must agree
+    //  Neither form
+    assert!(from_str(&TESTXMLDATE.replace("2006-02-01T14:29:53Z", "yesterday")).is_err());
+}
+
+#[test]
+fn xmlparsecdatatest1() {
+    //  CDATA content is literal markup, not parsed or unescaped.
+    const TESTXMLCDATA: &str = r#"
+<?xml version="1.0" encoding="UTF-8"?>
+<llsd>
+<map>
+<key><![CDATA[<k>]]></key><string><![CDATA[<b>bold</b> &amp;]]></string>
+</map>
+</llsd>
+"#;
+    let parsed = from_str(TESTXMLCDATA).unwrap();
+    assert_eq!(
+        parsed.as_map().unwrap().get("<k>"),
+        Some(&LLSDValue::String("<b>bold</b> &amp;".to_string()))
+    );
+}
+
+#[test]
+fn xmlparsecdatatest2() {
+    //  CDATA around numeric and boolean values parses to the typed value.
+    fn parse(body: &str) -> LLSDValue {
+        from_str(&format!("<llsd>{}</llsd>", body)).unwrap()
+    }
+    assert_eq!(parse("<integer><![CDATA[42]]></integer>"), LLSDValue::Integer(42));
+    assert_eq!(parse("<integer><![CDATA[ -7 ]]></integer>"), LLSDValue::Integer(-7));
+    assert_eq!(parse("<real><![CDATA[1.5e3]]></real>"), LLSDValue::Real(1500.0));
+    assert!(parse("<real><![CDATA[nan]]></real>").as_real().unwrap().is_nan());
+    assert_eq!(parse("<boolean><![CDATA[true]]></boolean>"), LLSDValue::Boolean(true));
+    assert_eq!(parse("<boolean><![CDATA[0]]></boolean>"), LLSDValue::Boolean(false));
+    assert_eq!(
+        parse("<array><integer><![CDATA[1]]></integer><boolean><![CDATA[1]]></boolean></array>"),
+        LLSDValue::Array(vec![LLSDValue::Integer(1), LLSDValue::Boolean(true)])
+    );
+    assert!(from_str("<llsd><integer><![CDATA[forty-two]]></integer></llsd>").is_err());
+}
+
+#[test]
+fn xmlparseuritest1() {
+    //  <uri> is a URI, not a String.
+    const TESTXMLURI: &str = r#"
+<?xml version="1.0" encoding="UTF-8"?>
+<llsd>
+<uri>http://example.com/a?b=c</uri>
+</llsd>
+"#;
+    assert_eq!(from_str(TESTXMLURI).unwrap(), LLSDValue::URI("http://example.com/a?b=c".to_string()));
+}
+
+#[test]
+fn xmlparselenientdatetest1() {
+    fn date_doc(date: &str) -> String {
+        format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<llsd><date>{}</date></llsd>", date)
+    }
+    let lenient = XmlOptions { lenient_dates: true, ..Default::default() };
+    let expected = LLSDValue::Date(1138804193.0);
+    for date in ["2006-02-01 14:29:53", "2006-02-01T14:29:53", "2006-02-01 14:29:53Z", "2006-02-01T16:29:53+02:00"] {
+        assert_eq!(from_str_with_options(&date_doc(date), &lenient).unwrap(), expected);
+    }
+    //  Strict is the default.
+    assert!(from_str(&date_doc("2006-02-01 14:29:53")).is_err());
+    assert!(from_str(&date_doc("2006-02-01T14:29:53")).is_err());
+}
+
+#[test]
+fn xmlparseleadingjunktest1() {
+    const TESTXML: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<llsd><integer>5</integer></llsd>";
+    let lenient = XmlOptions { skip_leading_junk: true, ..Default::default() };
+    //  Junk that looks like markup is an error by default.
+    let junky = format!("\u{1}<x>\n{}", TESTXML);
+    assert!(from_str(&junky).is_err());
+    assert_eq!(from_str_with_options(&junky, &lenient).unwrap(), LLSDValue::Integer(5));
+    //  No declaration, just <llsd>.
+    let junky = "<<junk<llsd><integer>5</integer></llsd>";
+    assert_eq!(from_str_with_options(junky, &lenient).unwrap(), LLSDValue::Integer(5));
+    //  Search is bounded.
+    let junky = format!("{}{}", "<".repeat(LLSDXMLJUNKLIMIT + 1), TESTXML);
+    assert!(from_str_with_options(&junky, &lenient).is_err());
+}
+
+#[test]
+fn xmlparsestreamtest1() {
+    use std::io::Read;
+    //  Generates a large XML array or map on demand, a few bytes per read,
+    //  so the full input never exists in memory at once. Each item is
+    //  followed by a long comment, so the input is much bigger than the tree.
+    struct ChunkedSource {
+        items: usize,      // number of items
+        map: bool,         // map with keys, rather than array
+        next_item: usize,  // next item to generate
+        pending: Vec<u8>,  // generated but not yet read
+        done: bool,
+        total: usize,      // bytes read so far
+    }
+    impl Read for ChunkedSource {
+        fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+            let tag: &[u8] = if self.map { b"map" } else { b"array" };
+            if self.pending.is_empty() && !self.done {
+                if self.next_item == 0 {
+                    self.pending.extend_from_slice(LLSDXMLPREFIX.as_bytes());
+                    self.pending.extend_from_slice(&[b"<", tag, b">\n"].concat());
+                }
+                if self.next_item < self.items {
+                    if self.map {
+                        self.pending.extend_from_slice(format!("<key>k{}</key>", self.next_item).as_bytes());
+                    }
+                    self.pending.extend_from_slice(format!("<integer>{}</integer>\n", self.next_item).as_bytes());
+                    self.pending.extend_from_slice(b"<!-- ");
+                    self.pending.extend_from_slice(&[b'x'; 1000]);
+                    self.pending.extend_from_slice(b" -->\n");
+                    self.next_item += 1;
+                } else {
+                    self.pending.extend_from_slice(&[b"</", tag, b">\n</llsd>\n"].concat());
+                    self.done = true;
+                }
+            }
+            let n = self.pending.len().min(out.len()).min(7); // short reads
+            out[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+            self.total += n;
+            Ok(n)
+        }
+    }
+    const ITEMS: usize = 10000;
+    for map in [false, true] {
+        let source = ChunkedSource { items: ITEMS, map, next_item: 0, pending: Vec::new(), done: false, total: 0 };
+        let mut rdr = BufReader::with_capacity(64, source);
+        let (parsed, peak) = crate::de::binary::peak_bytes(|| from_reader(&mut rdr).unwrap());
+        let input_size = rdr.get_ref().total;
+        if map {
+            let parsed = parsed.as_map().unwrap();
+            assert_eq!(parsed.len(), ITEMS);
+            assert!((0..ITEMS).all(|n| parsed[&format!("k{}", n)] == LLSDValue::Integer(n as i32)));
+        } else {
+            let parsed = parsed.as_array().unwrap();
+            assert_eq!(parsed.len(), ITEMS);
+            assert!(parsed.iter().enumerate().all(|(n, v)| *v.as_integer().unwrap() == n as i32));
+        }
+        //  Memory goes to the tree, not the raw input. Keeping every event's
+        //  bytes instead of one at a time would hold more than the whole input.
+        assert!(input_size > 10_000_000, "input only {} bytes", input_size);
+        assert!(peak < input_size / 3, "map {}: peak {} bytes for {} bytes of input", map, peak, input_size);
+    }
+}
+
+#[test]
+fn xmlparsebinarylentest1() {
+    fn parse(attrs: &str) -> Result<LLSDValue, Error> {
+        from_str(&format!("{}<binary {}>SGVsbG8gd29ybGQ=</binary></llsd>", LLSDXMLPREFIX, attrs))
+    }
+    let expected = LLSDValue::Binary(b"Hello world".to_vec());
+    assert_eq!(parse("len=\"11\"").unwrap(), expected);
+    assert_eq!(parse("encoding=\"base64\" len=\"11\"").unwrap(), expected);
+    assert_eq!(parse("").unwrap(), expected);
+    assert!(parse("len=\"12\"").is_err());
+    assert!(parse("len=\"eleven\"").is_err());
+}
+
+#[test]
+fn xmlparsefractionaldatetest1() {
+    const TESTXML: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<llsd><date>2006-02-01T14:29:53.250Z</date></llsd>";
+    let parsed = from_str(TESTXML).unwrap();
+    assert_eq!(parsed, LLSDValue::Date(1138804193.25));
+    //  Fractional seconds come back out.
+    let s = crate::ser::xml::to_string(&parsed, false).unwrap();
+    assert!(s.contains("<date>2006-02-01T14:29:53.250Z</date>"));
+    assert_eq!(from_str(&s).unwrap(), parsed);
+}
+
+#[test]
+fn xmlparsenamespacetest1() {
+    //  Default namespace, and mixed tab and space indentation.
+    const TESTXML1: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<llsd xmlns=\"http://www.secondlife.com/llsd\">\n  <map>\n\t<key>a</key>\n \t  <integer>1</integer>\n</map>\n</llsd>";
+    //  Namespace with a prefix on every tag.
+    const TESTXML2: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<ll:llsd xmlns:ll=\"http://www.secondlife.com/llsd\"><ll:map><ll:key>a</ll:key><ll:integer>1</ll:integer></ll:map></ll:llsd>";
+    let expected = crate::notation_from_str("{'a':i1}").unwrap();
+    assert_eq!(from_str(TESTXML1).unwrap(), expected);
+    assert_eq!(from_str(TESTXML2).unwrap(), expected);
+}
+
+#[test]
+fn xmlparsedepthtest1() {
+    use crate::de::DEFAULT_MAX_DEPTH;
+    fn nested(n: usize) -> String {
+        format!("<llsd>{}{}</llsd>", "<array>".repeat(n), "</array>".repeat(n))
+    }
+    assert!(from_str(&nested(DEFAULT_MAX_DEPTH)).is_ok());
+    let err = from_str(&nested(DEFAULT_MAX_DEPTH + 1)).unwrap_err();
+    assert!(err.to_string().contains("maximum nesting depth exceeded"));
+    let maps = |n: usize| format!("<llsd>{}<undef/>{}</llsd>", "<map><key>a</key>".repeat(n), "</map>".repeat(n));
+    assert!(from_str(&maps(DEFAULT_MAX_DEPTH)).is_ok());
+    assert!(from_str(&maps(DEFAULT_MAX_DEPTH + 1)).unwrap_err().to_string().contains("maximum nesting depth"));
+    //  Hostile input is an error, not a stack overflow.
+    assert!(from_str(&format!("<llsd>{}", "<array>".repeat(200_000))).is_err());
+    //  Maps count too.
+    let options = XmlOptions { max_depth: 2, ..Default::default() };
+    let doc = |inner: &str| format!("<llsd><map><key>a</key><array>{}</array></map></llsd>", inner);
+    assert!(from_str_with_options(&doc("<integer>1</integer>"), &options).is_ok());
+    assert!(from_str_with_options(&doc("<map></map>"), &options).is_err());
+}
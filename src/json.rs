@@ -0,0 +1,361 @@
+//! # json.rs
+//!
+//!  Conversion between LLSD value trees and serde_json values,
+//!  for handing LLSD data to JSON tooling.
+//!
+//!  JSON has no UUID, URI, date, or binary types, so those become
+//!  single-key objects tagged with the LLSD type:
+//!
+//!  - UUID: `{"__uuid__": "67153d5b-3659-afb4-8510-adda2c034649"}`
+//!  - URI: `{"__uri__": "http://example.com"}`
+//!  - Date: `{"__date__": 1138804193.5}`, seconds since 1970.
+//!  - Binary: `{"__binary__": "AAEC/v8="}`, standard base64.
+//!  - Real that is NaN or infinite: `{"__real__": "nan"}`, `"inf"`, or `"-inf"`.
+//!
+//!  Undefined is null. Integers and reals are JSON numbers; a JSON
+//!  number which is an integer fitting in an i32 comes back as Integer,
+//!  anything else as Real. An LLSD map which would look like a tag
+//!  is wrapped as `{"__map__": {...}}`, so every value round trips.
+//!
+//!  With `JsonOptions::binary_as_data_uri`, binary is instead a plain string,
+//!  `"data:application/octet-stream;base64,AAEC/v8="`, which some web tools
+//!  recognize. Converting back accepts base64 data URIs with any media type.
+//!  An LLSD string which would look like one is tagged as `{"__string__": "..."}`.
+//
+//  Animats
+//  October, 2026.
+//  License: LGPL.
+//
+use crate::{LLSDMap, LLSDValue};
+use anyhow::{anyhow, Error};
+use base64::Engine;
+use serde_json::{Map, Number, Value};
+use uuid::Uuid;
+
+const UUID_TAG: &str = "__uuid__";
+const URI_TAG: &str = "__uri__";
+const DATE_TAG: &str = "__date__";
+const BINARY_TAG: &str = "__binary__";
+const REAL_TAG: &str = "__real__";
+const MAP_TAG: &str = "__map__";
+const STRING_TAG: &str = "__string__";
+
+/// All tags. A single-key object with one of these keys is a tagged value.
+const TAGS: [&str; 7] = [UUID_TAG, URI_TAG, DATE_TAG, BINARY_TAG, REAL_TAG, MAP_TAG, STRING_TAG];
+
+/// Prefix for binary written as a data URI.
+const DATA_URI_PREFIX: &str = "data:application/octet-stream;base64,";
+
+/// Options for conversion to JSON.
+#[derive(Debug, Clone, Default)]
+pub struct JsonOptions {
+    /// Write Binary as a `data:application/octet-stream;base64,...` string,
+    /// rather than as a `{"__binary__": ...}` tagged object.
+    pub binary_as_data_uri: bool,
+}
+
+/// Convert to JSON, with options. The From conversion uses the default options.
+pub fn to_json_with_options(val: LLSDValue, options: &JsonOptions) -> Value {
+    match val {
+        LLSDValue::Undefined => Value::Null,
+        LLSDValue::Boolean(v) => Value::Bool(v),
+        LLSDValue::Integer(v) => Value::Number(v.into()),
+        LLSDValue::Real(v) => real_to_json(v),
+        LLSDValue::String(v) => {
+            if decode_data_uri(&v).is_some() {
+                tagged(STRING_TAG, Value::String(v))
+            } else {
+                Value::String(v)
+            }
+        }
+        LLSDValue::UUID(v) => tagged(UUID_TAG, Value::String(v.to_string())),
+        LLSDValue::URI(v) => tagged(URI_TAG, Value::String(v)),
+        LLSDValue::Date(v) => tagged(DATE_TAG, real_to_json(v)),
+        LLSDValue::Binary(v) => {
+            let encoded = base64::engine::general_purpose::STANDARD.encode(v);
+            if options.binary_as_data_uri {
+                Value::String(format!("{}{}", DATA_URI_PREFIX, encoded))
+            } else {
+                tagged(BINARY_TAG, Value::String(encoded))
+            }
+        }
+        LLSDValue::Array(v) => Value::Array(v.into_iter().map(|v| to_json_with_options(v, options)).collect()),
+        LLSDValue::Map(v) => {
+            let looks_tagged = v.len() == 1 && v.keys().all(|k| TAGS.contains(&k.as_str()));
+            let obj: Map<String, Value> = v
+                .into_iter()
+                .map(|(k, v)| (k, to_json_with_options(v, options)))
+                .collect();
+            if looks_tagged {
+                tagged(MAP_TAG, Value::Object(obj))
+            } else {
+                Value::Object(obj)
+            }
+        }
+    }
+}
+
+/// Bytes of a base64 data URI, such as "data:image/png;base64,...".
+/// None if the string is not one, or its base64 is bad.
+fn decode_data_uri(s: &str) -> Option<Vec<u8>> {
+    let (meta, data) = s.strip_prefix("data:")?.split_once(',')?;
+    if !meta.ends_with(";base64") {
+        return None;
+    }
+    base64::engine::general_purpose::STANDARD.decode(data).ok()
+}
+
+/// Single-key JSON object holding a tagged value.
+fn tagged(tag: &str, val: Value) -> Value {
+    let mut obj = Map::new();
+    obj.insert(tag.to_string(), val);
+    Value::Object(obj)
+}
+
+/// Real as JSON. Non-finite values have no JSON number form.
+fn real_to_json(v: f64) -> Value {
+    match Number::from_f64(v) {
+        Some(n) => Value::Number(n),
+        None => {
+            let s = if v.is_nan() {
+                "nan"
+            } else if v > 0.0 {
+                "inf"
+            } else {
+                "-inf"
+            };
+            tagged(REAL_TAG, Value::String(s.to_string()))
+        }
+    }
+}
+
+impl From<LLSDValue> for Value {
+    fn from(val: LLSDValue) -> Value {
+        to_json_with_options(val, &JsonOptions::default())
+    }
+}
+
+impl LLSDValue {
+    /// Canonical JSON text, for hashing. Object keys are sorted at every level,
+    /// there is no whitespace, and numbers are written the same way every time.
+    /// Uses the same tagging as the From conversion.
+    pub fn to_canonical_json(&self) -> Result<String, Error> {
+        let mut out = String::new();
+        write_canonical(&Value::from(self.clone()), &mut out)?;
+        Ok(out)
+    }
+}
+
+/// Write JSON with sorted keys, whatever order the map type keeps them in.
+fn write_canonical(val: &Value, out: &mut String) -> Result<(), Error> {
+    match val {
+        Value::Array(v) => {
+            out.push('[');
+            for (i, item) in v.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out)?;
+            }
+            out.push(']');
+        }
+        Value::Object(v) => {
+            let mut entries: Vec<(&String, &Value)> = v.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            out.push('{');
+            for (i, (key, item)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::to_string(key)?);
+                out.push(':');
+                write_canonical(item, out)?;
+            }
+            out.push('}');
+        }
+        _ => out.push_str(&serde_json::to_string(val)?),
+    }
+    Ok(())
+}
+
+/// Real from JSON, either a number or a tagged non-finite value.
+fn real_from_json(val: &Value) -> Result<f64, Error> {
+    match val {
+        Value::Number(n) => n.as_f64().ok_or_else(|| anyhow!("JSON number {} is not representable as a real", n)),
+        Value::Object(obj) => match obj.get(REAL_TAG).and_then(|v| v.as_str()) {
+            Some("nan") if obj.len() == 1 => Ok(f64::NAN),
+            Some("inf") if obj.len() == 1 => Ok(f64::INFINITY),
+            Some("-inf") if obj.len() == 1 => Ok(f64::NEG_INFINITY),
+            _ => Err(anyhow!("Expected a real, found {}", val)),
+        },
+        _ => Err(anyhow!("Expected a real, found {}", val)),
+    }
+}
+
+/// String contents of a tagged value.
+fn tag_str<'a>(tag: &str, val: &'a Value) -> Result<&'a str, Error> {
+    val.as_str()
+        .ok_or_else(|| anyhow!("JSON tag {} must hold a string, found {}", tag, val))
+}
+
+/// Decode a single-key object whose key is a tag.
+fn from_tagged(tag: &str, val: Value) -> Result<LLSDValue, Error> {
+    match tag {
+        UUID_TAG => Ok(LLSDValue::UUID(Uuid::parse_str(tag_str(tag, &val)?)?)),
+        URI_TAG => Ok(LLSDValue::URI(tag_str(tag, &val)?.to_string())),
+        DATE_TAG => Ok(LLSDValue::Date(real_from_json(&val)?)),
+        BINARY_TAG => Ok(LLSDValue::Binary(
+            base64::engine::general_purpose::STANDARD.decode(tag_str(tag, &val)?)?,
+        )),
+        REAL_TAG => Ok(LLSDValue::Real(real_from_json(&tagged(REAL_TAG, val))?)),
+        STRING_TAG => Ok(LLSDValue::String(tag_str(tag, &val)?.to_string())),
+        MAP_TAG => match val {
+            Value::Object(obj) => Ok(LLSDValue::Map(map_from_json(obj)?)),
+            _ => Err(anyhow!("JSON tag {} must hold an object, found {}", tag, val)),
+        },
+        _ => Err(anyhow!("Unknown JSON tag {}", tag)),
+    }
+}
+
+/// Plain JSON object to LLSD map.
+fn map_from_json(obj: Map<String, Value>) -> Result<LLSDMap, Error> {
+    obj.into_iter()
+        .map(|(k, v)| Ok((k, LLSDValue::try_from(v)?)))
+        .collect()
+}
+
+impl TryFrom<Value> for LLSDValue {
+    type Error = Error;
+    /// Fails on malformed tagged values, such as a bad UUID or bad base64.
+    /// Strings which are base64 data URIs become Binary.
+    fn try_from(val: Value) -> Result<LLSDValue, Error> {
+        match val {
+            Value::Null => Ok(LLSDValue::Undefined),
+            Value::Bool(v) => Ok(LLSDValue::Boolean(v)),
+            Value::Number(ref n) => match n.as_i64().map(i32::try_from) {
+                Some(Ok(v)) => Ok(LLSDValue::Integer(v)),
+                _ => Ok(LLSDValue::Real(real_from_json(&val)?)),
+            },
+            Value::String(v) => match decode_data_uri(&v) {
+                Some(bytes) => Ok(LLSDValue::Binary(bytes)),
+                None => Ok(LLSDValue::String(v)),
+            },
+            Value::Array(v) => Ok(LLSDValue::Array(
+                v.into_iter().map(LLSDValue::try_from).collect::<Result<_, _>>()?,
+            )),
+            Value::Object(mut obj) => {
+                let tag = if obj.len() == 1 {
+                    obj.keys().find(|k| TAGS.contains(&k.as_str())).cloned()
+                } else {
+                    None
+                };
+                match tag {
+                    Some(tag) => {
+                        let inner = obj.remove(&tag).unwrap(); // key was just found
+                        from_tagged(&tag, inner)
+                    }
+                    None => Ok(LLSDValue::Map(map_from_json(obj)?)),
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn jsonroundtriptest1() {
+    let val = LLSDValue::example();
+    let json = Value::from(val.clone());
+    assert_eq!(json["uuid"]["__uuid__"], "67153d5b-3659-afb4-8510-adda2c034649");
+    assert_eq!(json["binary"]["__binary__"], "AAEC/v8=");
+    assert_eq!(json["integer"], -42);
+    //  Through JSON text and back.
+    let text = serde_json::to_string(&json).unwrap();
+    let back = LLSDValue::try_from(serde_json::from_str::<Value>(&text).unwrap()).unwrap();
+    assert_eq!(back, val);
+}
+
+#[test]
+fn jsonroundtriptest2() {
+    //  Non-finite reals, fractional dates, and maps that look like tags.
+    let lookalike: LLSDMap = [("__uuid__".to_string(), LLSDValue::String("not a uuid".to_string()))]
+        .into_iter()
+        .collect();
+    let val = LLSDValue::Array(vec![
+        LLSDValue::Real(f64::INFINITY),
+        LLSDValue::Real(f64::NEG_INFINITY),
+        LLSDValue::Real(1.0),
+        LLSDValue::Date(1138804193.25),
+        LLSDValue::Map(lookalike),
+    ]);
+    let text = serde_json::to_string(&Value::from(val.clone())).unwrap();
+    let back = LLSDValue::try_from(serde_json::from_str::<Value>(&text).unwrap()).unwrap();
+    assert_eq!(back, val);
+    let nan = LLSDValue::try_from(Value::from(LLSDValue::Real(f64::NAN))).unwrap();
+    assert!(nan.as_real().unwrap().is_nan());
+    //  Malformed tags are errors.
+    assert!(LLSDValue::try_from(serde_json::json!({"__uuid__": "xyz"})).is_err());
+    assert!(LLSDValue::try_from(serde_json::json!({"__binary__": 12})).is_err());
+    //  Integers too big for an i32 become reals.
+    assert_eq!(LLSDValue::try_from(serde_json::json!(5000000000i64)).unwrap(), LLSDValue::Real(5e9));
+}
+
+#[test]
+fn jsondatauritest1() {
+    let options = JsonOptions { binary_as_data_uri: true };
+    let val = LLSDValue::example();
+    let json = to_json_with_options(val.clone(), &options);
+    assert_eq!(json["binary"], "data:application/octet-stream;base64,AAEC/v8=");
+    assert_eq!(json["array"][8], "data:application/octet-stream;base64,");
+    let text = serde_json::to_string(&json).unwrap();
+    let back = LLSDValue::try_from(serde_json::from_str::<Value>(&text).unwrap()).unwrap();
+    assert_eq!(back, val);
+    //  Default is still the tagged form.
+    assert_eq!(Value::from(LLSDValue::Binary(vec![1]))["__binary__"], "AQ==");
+    //  Data URIs from elsewhere, with any media type.
+    let json = serde_json::json!(["data:image/png;base64,iVBORw==", "data:text/plain,hello", "data:x;base64,!!"]);
+    assert_eq!(
+        LLSDValue::try_from(json).unwrap(),
+        LLSDValue::Array(vec![
+            LLSDValue::Binary(vec![0x89, b'P', b'N', b'G']),
+            LLSDValue::String("data:text/plain,hello".to_string()),
+            LLSDValue::String("data:x;base64,!!".to_string()),
+        ])
+    );
+    //  A string which looks like a data URI stays a string, in both modes.
+    let lookalike = LLSDValue::String("data:application/octet-stream;base64,AQ==".to_string());
+    for options in [JsonOptions::default(), options] {
+        let json = to_json_with_options(lookalike.clone(), &options);
+        assert_eq!(LLSDValue::try_from(json).unwrap(), lookalike);
+    }
+}
+
+#[test]
+fn jsoncanonicaltest1() {
+    fn entry(k: &str) -> (String, LLSDValue) {
+        let inner: LLSDMap = [
+            ("b".to_string(), LLSDValue::Real(1.5)),
+            ("a".to_string(), LLSDValue::String(k.to_string())),
+        ]
+        .into_iter()
+        .collect();
+        (k.to_string(), LLSDValue::Map(inner))
+    }
+    let keys: Vec<String> = (0..50).map(|n| format!("k{}", n)).collect();
+    let forward = LLSDValue::Map(keys.iter().map(|k| entry(k)).collect());
+    let backward = LLSDValue::Map(keys.iter().rev().map(|k| entry(k)).collect());
+    assert_eq!(forward.to_canonical_json().unwrap(), backward.to_canonical_json().unwrap());
+    //  Compact, sorted, and tagged the same as the From conversion.
+    let val = LLSDValue::Map(
+        [
+            ("z".to_string(), LLSDValue::Array(vec![LLSDValue::Undefined, LLSDValue::Integer(2)])),
+            ("a".to_string(), LLSDValue::UUID(Uuid::nil())),
+            ("m".to_string(), LLSDValue::Real(0.25)),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    assert_eq!(
+        val.to_canonical_json().unwrap(),
+        r#"{"a":{"__uuid__":"00000000-0000-0000-0000-000000000000"},"m":0.25,"z":[null,2]}"#
+    );
+}
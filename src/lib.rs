@@ -16,30 +16,49 @@
 //
 pub mod de;
 pub mod ser;
+pub mod util;
+#[cfg(feature = "json")]
+pub mod json;
 
 pub use crate::{
     de::{
-        auto_from_bytes, auto_from_str,
+        auto_from_bytes, auto_from_str, read_file,
         binary::from_bytes as binary_from_bytes,
         binary::from_reader as binary_from_reader, // Name clash
         xml::from_reader,
         xml::from_str,
         notation::from_bytes as notation_from_bytes,
         notation::from_str as notation_from_str,
+        serde_impl::from_value,
     },
     ser::{
+        write_file,
         binary::to_bytes,
         binary::to_writer as binary_to_writer, // Name clash
         xml::to_string,
         xml::to_writer,
         notation::to_string as notation_to_string, // Name clash
+        notation::to_writer as notation_to_writer, // Name clash
+        serde_impl::to_value,
     },
 };
 
+use anyhow::{anyhow, Error};
 use enum_as_inner::EnumAsInner;
 use std::collections::HashMap;
 use uuid::Uuid;
 
+/// Map type used by LLSDValue::Map.
+/// With the "ordered-map" feature, this is a BTreeMap, and
+/// serializers emit keys in sorted order, the same on every run.
+#[cfg(not(feature = "ordered-map"))]
+pub type LLSDMap = HashMap<String, LLSDValue>;
+/// Map type used by LLSDValue::Map.
+/// With the "ordered-map" feature, this is a BTreeMap, and
+/// serializers emit keys in sorted order, the same on every run.
+#[cfg(feature = "ordered-map")]
+pub type LLSDMap = std::collections::BTreeMap<String, LLSDValue>;
+
 /// The primitive LLSD data item.
 /// Serialization takes a tree of these.
 /// Deserialization returns a tree of these.
@@ -58,13 +77,1334 @@ pub enum LLSDValue {
     /// String, UTF-8.
     String(String),
     /// Date, as seconds relative to the UNIX epoch, January 1, 1970.
-    Date(i64),
+    /// Fractional seconds are kept.
+    Date(f64),
     /// Universal Resource Identifier
     URI(String),
     /// Array of bytes.
     Binary(Vec<u8>),
     /// Key/value set of more LLSDValue items.
-    Map(HashMap<String, LLSDValue>),
+    Map(LLSDMap),
     /// Array of more LLSDValue items.
     Array(Vec<LLSDValue>),
 }
+
+/// The three LLSD serialization formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// XML, with <?xml ... ?> header.
+    Xml,
+    /// Binary, with <? LLSD/Binary ?> header.
+    Binary,
+    /// Notation, with <? llsd/notation ?> header.
+    Notation,
+}
+
+/// The LLSD data types, without values.
+/// Used to name a target type for conversions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LLSDType {
+    Undefined,
+    Boolean,
+    Real,
+    Integer,
+    UUID,
+    String,
+    Date,
+    URI,
+    Binary,
+    Map,
+    Array,
+}
+
+/// An LLSD number, which is either an Integer or a Real.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Number {
+    /// Integer, 32 bit.
+    Int(i32),
+    /// Real, 64 bit.
+    Real(f64),
+}
+
+/// One edit for LLSDValue::apply_patch.
+/// Paths are slash-delimited, like JSON pointers: "/agent/0/name".
+/// "~1" in a path component means "/", and "~0" means "~".
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatchOp {
+    /// Set the value at path. The parent must exist.
+    /// For an array parent, the index may be one past the end, to append.
+    Set { path: String, value: LLSDValue },
+    /// Remove the value at path, which must exist.
+    Remove { path: String },
+}
+
+/// How merge handles arrays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArrayMerge {
+    /// The new array replaces the old one.
+    #[default]
+    Replace,
+    /// Merge element by element. Extra new elements are appended.
+    Index,
+}
+
+/// Options for merge.
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    /// How arrays are merged.
+    pub array_strategy: ArrayMerge,
+}
+
+impl LLSDValue {
+    /// New empty map.
+    pub fn new_map() -> LLSDValue {
+        LLSDValue::Map(LLSDMap::new())
+    }
+
+    /// New empty array.
+    pub fn new_array() -> LLSDValue {
+        LLSDValue::Array(Vec::new())
+    }
+
+    /// Real, rejecting NaN and infinities, which break equality and text round trips.
+    pub fn real(value: f64) -> Result<LLSDValue, Error> {
+        if value.is_finite() {
+            Ok(LLSDValue::Real(value))
+        } else {
+            Err(anyhow!("Real value {} is not finite", value))
+        }
+    }
+
+    /// Real, with NaN and infinities replaced by 0.0.
+    pub fn real_or_zero(value: f64) -> LLSDValue {
+        LLSDValue::Real(if value.is_finite() { value } else { 0.0 })
+    }
+
+    /// Insert a map entry and return self, for building maps by chaining.
+    /// If self is not a map, it is replaced by an empty map first.
+    pub fn with_key(mut self, key: impl Into<String>, value: impl Into<LLSDValue>) -> Self {
+        if !matches!(self, LLSDValue::Map(_)) {
+            self = LLSDValue::new_map();
+        }
+        if let LLSDValue::Map(v) = &mut self {
+            let _ = v.insert(key.into(), value.into());
+        }
+        self
+    }
+
+    /// Append an array element and return self, for building arrays by chaining.
+    /// If self is not an array, it is replaced by an empty array first.
+    pub fn with_element(mut self, value: impl Into<LLSDValue>) -> Self {
+        if !matches!(self, LLSDValue::Array(_)) {
+            self = LLSDValue::new_array();
+        }
+        if let LLSDValue::Array(v) = &mut self {
+            v.push(value.into());
+        }
+        self
+    }
+
+    /// Map value for key. None if missing or this is not a map.
+    pub fn get(&self, key: &str) -> Option<&LLSDValue> {
+        match self {
+            LLSDValue::Map(v) => v.get(key),
+            _ => None,
+        }
+    }
+
+    /// Array element. None if out of range or this is not an array.
+    pub fn index(&self, i: usize) -> Option<&LLSDValue> {
+        match self {
+            LLSDValue::Array(v) => v.get(i),
+            _ => None,
+        }
+    }
+
+    /// Map value for key, or the default if missing or this is not a map.
+    pub fn get_or<'a>(&'a self, key: &str, default: &'a LLSDValue) -> &'a LLSDValue {
+        self.get(key).unwrap_or(default)
+    }
+
+    /// A populated value with one of each variant, nested in a map and an array.
+    /// Test data for serializer round trips.
+    #[cfg(test)]
+    pub(crate) fn example() -> LLSDValue {
+        LLSDValue::Map(
+            [
+                ("undef", LLSDValue::Undefined),
+                ("boolean", LLSDValue::Boolean(true)),
+                ("real", LLSDValue::Real(-1.5e10)),
+                ("integer", LLSDValue::Integer(-42)),
+                ("uuid", LLSDValue::UUID(Uuid::parse_str("67153d5b-3659-afb4-8510-adda2c034649").unwrap())),
+                ("string", LLSDValue::String("Hello \"world\" it's <&>".to_string())),
+                ("date", LLSDValue::Date(1138804193.0)),
+                ("uri", LLSDValue::URI("http://example.com/a b?c=d&e".to_string())),
+                ("binary", LLSDValue::Binary(vec![0, 1, 2, 0xfe, 0xff])),
+                ("map", LLSDValue::Map([("inner".to_string(), LLSDValue::Integer(1))].into_iter().collect())),
+                (
+                    "array",
+                    LLSDValue::Array(vec![
+                        LLSDValue::Undefined,
+                        LLSDValue::Boolean(false),
+                        LLSDValue::Real(0.25),
+                        LLSDValue::Integer(0),
+                        LLSDValue::UUID(Uuid::nil()),
+                        LLSDValue::String(String::new()),
+                        LLSDValue::Date(0.0),
+                        LLSDValue::URI(String::new()),
+                        LLSDValue::Binary(Vec::new()),
+                        LLSDValue::new_map(),
+                        LLSDValue::new_array(),
+                    ]),
+                ),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect(),
+        )
+    }
+
+    /// Binary value from base64 text. Whitespace, such as line breaks, is ignored.
+    pub fn binary_from_base64(s: &str) -> Result<LLSDValue, Error> {
+        use base64::Engine;
+        let mut s = s.to_string();
+        s.retain(|c| !c.is_ascii_whitespace());
+        Ok(LLSDValue::Binary(base64::engine::general_purpose::STANDARD.decode(s)?))
+    }
+
+    /// Binary value as base64 text. None if not Binary.
+    pub fn binary_as_base64(&self) -> Option<String> {
+        use base64::Engine;
+        self.as_binary()
+            .map(|v| base64::engine::general_purpose::STANDARD.encode(v))
+    }
+
+    /// Binary value as text, for binary fields that hold UTF-8.
+    /// None if not Binary, or not valid UTF-8.
+    pub fn binary_as_string(&self) -> Option<&str> {
+        std::str::from_utf8(self.as_binary()?).ok()
+    }
+
+    /// Name of this value's type, as used in LLSD XML.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            LLSDValue::Undefined => "undef",
+            LLSDValue::Boolean(_) => "boolean",
+            LLSDValue::Real(_) => "real",
+            LLSDValue::Integer(_) => "integer",
+            LLSDValue::UUID(_) => "uuid",
+            LLSDValue::String(_) => "string",
+            LLSDValue::Date(_) => "date",
+            LLSDValue::URI(_) => "uri",
+            LLSDValue::Binary(_) => "binary",
+            LLSDValue::Map(_) => "map",
+            LLSDValue::Array(_) => "array",
+        }
+    }
+
+    /// Take the map, or a descriptive error if this is not a map.
+    /// (The derived into_map returns the original value on mismatch instead.)
+    pub fn try_into_map(self) -> Result<LLSDMap, Error> {
+        match self {
+            LLSDValue::Map(v) => Ok(v),
+            other => Err(anyhow!("Expected LLSD map, found {}", other.type_name())),
+        }
+    }
+
+    /// Take the array, or a descriptive error if this is not an array.
+    pub fn try_into_array(self) -> Result<Vec<LLSDValue>, Error> {
+        match self {
+            LLSDValue::Array(v) => Ok(v),
+            other => Err(anyhow!("Expected LLSD array, found {}", other.type_name())),
+        }
+    }
+
+    /// Truncate or pad an array to exactly len elements, filling new slots with fill.
+    /// For SL's fixed-length vectors and quaternions. Error if not an array.
+    pub fn resize_array(&mut self, len: usize, fill: LLSDValue) -> Result<(), Error> {
+        match self {
+            LLSDValue::Array(v) => {
+                v.resize(len, fill);
+                Ok(())
+            }
+            other => Err(anyhow!("Cannot resize {} as an array", other.type_name())),
+        }
+    }
+
+    /// Number of elements, if this is an array.
+    pub fn array_len(&self) -> Option<usize> {
+        self.as_array().map(|v| v.len())
+    }
+
+    /// Elements of an array, or just this value if it is not an array.
+    /// For fields SL sends as either one value or an array, such as "sides".
+    pub fn as_array_coerced(&self) -> Vec<&LLSDValue> {
+        match self {
+            LLSDValue::Array(v) => v.iter().collect(),
+            _ => vec![self],
+        }
+    }
+
+    /// Number of entries, if this is a map.
+    pub fn map_len(&self) -> Option<usize> {
+        self.as_map().map(|v| v.len())
+    }
+
+    /// Number of bytes, if this is Binary.
+    pub fn binary_len(&self) -> Option<usize> {
+        self.as_binary().map(|v| v.len())
+    }
+
+    /// Length in bytes of the UTF-8, if this is a String or URI.
+    pub fn string_len(&self) -> Option<usize> {
+        match self {
+            LLSDValue::String(v) | LLSDValue::URI(v) => Some(v.len()),
+            _ => None,
+        }
+    }
+
+    /// Maximum nesting depth of the tree.
+    /// Scalars are depth 0. A container is 1 more than its deepest child,
+    /// so an empty container is 1.
+    /// Iterative, so deep trees will not overflow the call stack.
+    pub fn depth(&self) -> usize {
+        let mut max_depth = 0;
+        let mut stack: Vec<(&LLSDValue, usize)> = vec![(self, 0)];
+        while let Some((val, depth)) = stack.pop() {
+            match val {
+                LLSDValue::Array(v) => {
+                    max_depth = max_depth.max(depth + 1);
+                    stack.extend(v.iter().map(|child| (child, depth + 1)));
+                }
+                LLSDValue::Map(v) => {
+                    max_depth = max_depth.max(depth + 1);
+                    stack.extend(v.values().map(|child| (child, depth + 1)));
+                }
+                _ => max_depth = max_depth.max(depth),
+            }
+        }
+        max_depth
+    }
+
+    /// The number, if this is an Integer or a Real.
+    /// Keeps the distinction between the two.
+    pub fn as_number(&self) -> Option<Number> {
+        match self {
+            LLSDValue::Integer(v) => Some(Number::Int(*v)),
+            LLSDValue::Real(v) => Some(Number::Real(*v)),
+            _ => None,
+        }
+    }
+
+    /// New Date from an RFC 3339 date string, such as "2006-02-01T14:29:53Z".
+    pub fn date_from_rfc3339(s: &str) -> Result<LLSDValue, Error> {
+        Ok(LLSDValue::Date(de::parse_rfc3339_date(s, false)?))
+    }
+
+    /// Any scalar as a string, for logging and display.
+    /// Undefined is the empty string, as in SL. Containers are None.
+    pub fn as_str_lossy(&self) -> Option<String> {
+        use base64::Engine;
+        match self {
+            LLSDValue::Undefined => Some(String::new()),
+            LLSDValue::Boolean(v) => Some(v.to_string()),
+            LLSDValue::Real(v) => Some(v.to_string()),
+            LLSDValue::Integer(v) => Some(v.to_string()),
+            LLSDValue::UUID(v) => Some(v.to_string()),
+            LLSDValue::String(v) | LLSDValue::URI(v) => Some(v.clone()),
+            LLSDValue::Date(v) => Some(match ser::format_rfc3339_date(*v) {
+                Some(date) => date,
+                None => v.to_string(), // out of range for a date, just show the number
+            }),
+            LLSDValue::Binary(v) => Some(base64::engine::general_purpose::STANDARD.encode(v)),
+            LLSDValue::Map(_) | LLSDValue::Array(_) => None,
+        }
+    }
+
+    /// True only for a UUID which is the nil UUID.
+    /// SL uses the nil UUID as "none".
+    pub fn is_nil_uuid(&self) -> bool {
+        matches!(self, LLSDValue::UUID(v) if v.is_nil())
+    }
+
+    /// The UUID, also parsing a String or URI which holds one.
+    /// SL sometimes stores UUIDs as strings, such as material override texture IDs.
+    /// None if this is not a UUID and does not parse as one.
+    pub fn as_uuid_lenient(&self) -> Option<Uuid> {
+        match self {
+            LLSDValue::UUID(v) => Some(*v),
+            LLSDValue::String(v) | LLSDValue::URI(v) => Uuid::parse_str(v.trim()).ok(),
+            _ => None,
+        }
+    }
+
+    /// The UUID, or the nil UUID if this is not a UUID.
+    /// This is how SL treats missing UUIDs.
+    pub fn as_uuid_or_nil(&self) -> Uuid {
+        match self {
+            LLSDValue::UUID(v) => *v,
+            _ => Uuid::nil(),
+        }
+    }
+
+    /// Reference to the value at a slash-delimited path, like "/agent/0/name".
+    /// The empty path is the whole value. None if the path does not exist.
+    pub fn pointer(&self, path: &str) -> Option<&LLSDValue> {
+        let mut val = self;
+        for token in pointer_tokens(path)? {
+            val = match val {
+                LLSDValue::Map(v) => v.get(&token)?,
+                LLSDValue::Array(v) => v.get(token.parse::<usize>().ok()?)?,
+                _ => return None,
+            };
+        }
+        Some(val)
+    }
+
+    /// True if a slash-delimited path, like "/agent/0/name", leads to a value.
+    pub fn path_exists(&self, path: &str) -> bool {
+        self.pointer(path).is_some()
+    }
+
+    /// Mutable reference to the value at a slash-delimited path, like "/agent/0/name".
+    /// The empty path is the whole value. None if the path does not exist.
+    pub fn pointer_mut(&mut self, path: &str) -> Option<&mut LLSDValue> {
+        let mut val = self;
+        for token in pointer_tokens(path)? {
+            val = match val {
+                LLSDValue::Map(v) => v.get_mut(&token)?,
+                LLSDValue::Array(v) => v.get_mut(token.parse::<usize>().ok()?)?,
+                _ => return None,
+            };
+        }
+        Some(val)
+    }
+
+    /// Apply a list of edits, in order.
+    /// Stops at the first edit which fails. Edits before that remain applied.
+    pub fn apply_patch(&mut self, ops: &[PatchOp]) -> Result<(), Error> {
+        for op in ops {
+            match op {
+                PatchOp::Set { path, value } => {
+                    let mut tokens = pointer_tokens(path).ok_or_else(|| anyhow!("Bad path: {:?}", path))?;
+                    let last = match tokens.pop() {
+                        Some(last) => last,
+                        None => {
+                            *self = value.clone(); // empty path, whole value
+                            continue;
+                        }
+                    };
+                    let parent = self
+                        .pointer_mut(&tokens_to_pointer(&tokens))
+                        .ok_or_else(|| anyhow!("Set: parent of {:?} does not exist", path))?;
+                    match parent {
+                        LLSDValue::Map(v) => {
+                            let _ = v.insert(last, value.clone());
+                        }
+                        LLSDValue::Array(v) => match last.parse::<usize>() {
+                            Ok(n) if n < v.len() => v[n] = value.clone(),
+                            Ok(n) if n == v.len() => v.push(value.clone()),
+                            _ => return Err(anyhow!("Set: array index out of range in {:?}", path)),
+                        },
+                        _ => return Err(anyhow!("Set: parent of {:?} is not a map or array", path)),
+                    }
+                }
+                PatchOp::Remove { path } => {
+                    let mut tokens = pointer_tokens(path).ok_or_else(|| anyhow!("Bad path: {:?}", path))?;
+                    let last = tokens
+                        .pop()
+                        .ok_or_else(|| anyhow!("Remove: cannot remove the whole value"))?;
+                    let removed = match self.pointer_mut(&tokens_to_pointer(&tokens)) {
+                        Some(LLSDValue::Map(v)) => v.remove(&last).is_some(),
+                        Some(LLSDValue::Array(v)) => match last.parse::<usize>() {
+                            Ok(n) if n < v.len() => {
+                                let _ = v.remove(n);
+                                true
+                            }
+                            _ => false,
+                        },
+                        _ => false,
+                    };
+                    if !removed {
+                        return Err(anyhow!("Remove: {:?} does not exist", path));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Edits which turn self into new, for apply_patch.
+    /// Maps are compared key by key, recursively. Any other difference,
+    /// including in an array, replaces the whole value at that path.
+    /// Empty if the two are equal.
+    pub fn diff(&self, new: &LLSDValue) -> Vec<PatchOp> {
+        fn walk(old: &LLSDValue, new: &LLSDValue, path: &mut Vec<String>, ops: &mut Vec<PatchOp>) {
+            match (old, new) {
+                (LLSDValue::Map(a), LLSDValue::Map(b)) => {
+                    for k in a.keys().filter(|k| !b.contains_key(*k)) {
+                        path.push(k.clone());
+                        ops.push(PatchOp::Remove { path: tokens_to_pointer(path) });
+                        let _ = path.pop();
+                    }
+                    for (k, v) in b {
+                        path.push(k.clone());
+                        match a.get(k) {
+                            Some(oldv) => walk(oldv, v, path, ops),
+                            None => ops.push(PatchOp::Set { path: tokens_to_pointer(path), value: v.clone() }),
+                        }
+                        let _ = path.pop();
+                    }
+                }
+                _ if old == new => {}
+                _ => ops.push(PatchOp::Set { path: tokens_to_pointer(path), value: new.clone() }),
+            }
+        }
+        let mut ops = Vec::new();
+        walk(self, new, &mut Vec::new(), &mut ops);
+        ops
+    }
+
+    /// Rename a map key, keeping its value. Replaces any existing entry for the new key.
+    /// Returns true if the key was present and renamed.
+    pub fn rename_key(&mut self, from: &str, to: &str) -> bool {
+        if let LLSDValue::Map(v) = self {
+            if let Some(val) = v.remove(from) {
+                let _ = v.insert(to.to_string(), val);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Rename a map key in every map in the tree.
+    /// Returns the number of keys renamed.
+    pub fn rename_key_recursive(&mut self, from: &str, to: &str) -> usize {
+        let mut count = 0;
+        let mut stack: Vec<&mut LLSDValue> = vec![self];
+        while let Some(val) = stack.pop() {
+            if val.rename_key(from, to) {
+                count += 1;
+            }
+            match val {
+                LLSDValue::Map(v) => stack.extend(v.values_mut()),
+                LLSDValue::Array(v) => stack.extend(v.iter_mut()),
+                _ => {}
+            }
+        }
+        count
+    }
+
+    /// Replace every NaN or infinite real in the tree with the given value.
+    /// Useful before text serialization, where non-finite reals are format-specific.
+    /// Returns the number of reals replaced.
+    pub fn replace_nonfinite(&mut self, replacement: LLSDValue) -> usize {
+        let mut count = 0;
+        let mut stack: Vec<&mut LLSDValue> = vec![self];
+        while let Some(val) = stack.pop() {
+            match val {
+                LLSDValue::Real(v) if !v.is_finite() => {
+                    *val = replacement.clone();
+                    count += 1;
+                }
+                LLSDValue::Map(v) => stack.extend(v.values_mut()),
+                LLSDValue::Array(v) => stack.extend(v.iter_mut()),
+                _ => {}
+            }
+        }
+        count
+    }
+
+    /// Replace the value of every map entry whose key is in `keys` with String("***"),
+    /// anywhere in the tree. For scrubbing passwords and session IDs before logging.
+    /// Redacted values are not searched further.
+    /// Returns the number of values redacted.
+    pub fn redact(&mut self, keys: &[&str]) -> usize {
+        let mut count = 0;
+        let mut stack: Vec<&mut LLSDValue> = vec![self];
+        while let Some(val) = stack.pop() {
+            match val {
+                LLSDValue::Map(v) => {
+                    for (k, item) in v.iter_mut() {
+                        if keys.contains(&k.as_str()) {
+                            *item = LLSDValue::String("***".to_string());
+                            count += 1;
+                        } else {
+                            stack.push(item);
+                        }
+                    }
+                }
+                LLSDValue::Array(v) => stack.extend(v.iter_mut()),
+                _ => {}
+            }
+        }
+        count
+    }
+
+    /// Compare two trees, treating a missing map key and an Undefined value as equal.
+    /// Otherwise the same as ==.
+    pub fn equivalent_ignoring_undefined(&self, other: &LLSDValue) -> bool {
+        match (self, other) {
+            (LLSDValue::Map(a), LLSDValue::Map(b)) => {
+                let undef = LLSDValue::Undefined;
+                a.keys()
+                    .chain(b.keys().filter(|k| !a.contains_key(*k)))
+                    .all(|k| {
+                        a.get(k)
+                            .unwrap_or(&undef)
+                            .equivalent_ignoring_undefined(b.get(k).unwrap_or(&undef))
+                    })
+            }
+            (LLSDValue::Array(a), LLSDValue::Array(b)) => {
+                a.len() == b.len()
+                    && a.iter().zip(b).all(|(x, y)| x.equivalent_ignoring_undefined(y))
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    /// First node in the tree, self included, for which pred is true.
+    /// Depth first, array elements in order. Map entries are searched in map order.
+    pub fn find(&self, pred: impl Fn(&LLSDValue) -> bool) -> Option<&LLSDValue> {
+        let mut stack: Vec<&LLSDValue> = vec![self];
+        while let Some(val) = stack.pop() {
+            if pred(val) {
+                return Some(val);
+            }
+            push_children(&mut stack, val);
+        }
+        None
+    }
+
+    /// All nodes in the tree, self included, for which pred is true, in the order find visits them.
+    pub fn find_all(&self, pred: impl Fn(&LLSDValue) -> bool) -> Vec<&LLSDValue> {
+        let mut found = Vec::new();
+        let mut stack: Vec<&LLSDValue> = vec![self];
+        while let Some(val) = stack.pop() {
+            if pred(val) {
+                found.push(val);
+            }
+            push_children(&mut stack, val);
+        }
+        found
+    }
+
+    /// How many nodes of each type are in the tree, self included, keyed by type_name.
+    /// For finding out what dominates a large document.
+    pub fn type_histogram(&self) -> HashMap<&'static str, usize> {
+        let mut counts = HashMap::new();
+        let mut stack: Vec<&LLSDValue> = vec![self];
+        while let Some(val) = stack.pop() {
+            *counts.entry(val.type_name()).or_insert(0) += 1;
+            match val {
+                LLSDValue::Map(v) => stack.extend(v.values()),
+                LLSDValue::Array(v) => stack.extend(v.iter()),
+                _ => {}
+            }
+        }
+        counts
+    }
+
+    /// Merge another value into this one.
+    /// Maps are merged key by key, recursively. Anything else is replaced.
+    pub fn merge(&mut self, other: LLSDValue) {
+        self.merge_with_options(other, &MergeOptions::default())
+    }
+
+    /// Merge another value into this one, with options.
+    pub fn merge_with_options(&mut self, other: LLSDValue, options: &MergeOptions) {
+        match (self, other) {
+            (LLSDValue::Map(old), LLSDValue::Map(new)) => {
+                for (k, v) in new {
+                    match old.get_mut(&k) {
+                        Some(oldv) => oldv.merge_with_options(v, options),
+                        None => {
+                            let _ = old.insert(k, v);
+                        }
+                    }
+                }
+            }
+            (LLSDValue::Array(old), LLSDValue::Array(new))
+                if options.array_strategy == ArrayMerge::Index =>
+            {
+                for (i, v) in new.into_iter().enumerate() {
+                    if i < old.len() {
+                        old[i].merge_with_options(v, options);
+                    } else {
+                        old.push(v);
+                    }
+                }
+            }
+            (oldv, new) => *oldv = new,
+        }
+    }
+
+    /// Put every map in the tree in key order, so the value itself is canonical,
+    /// not just its serialization.
+    /// With the "ordered-map" feature, maps are BTreeMaps, which are always in key order,
+    /// so there is nothing to do. With the default HashMap, maps have no order to set,
+    /// and this is also a no-op; enable "ordered-map" for sorted keys.
+    pub fn sort_keys_recursive(&mut self) {}
+
+    /// Apply a function to every node of the tree, bottom up,
+    /// consuming the tree and returning the transformed tree.
+    /// Children are transformed before their parent container sees them.
+    /// Uses an explicit stack, so deep trees will not overflow the call stack.
+    pub fn map_recursive(self, mut f: impl FnMut(LLSDValue) -> LLSDValue) -> LLSDValue {
+        //  A container partly rebuilt: finished children, remaining children.
+        enum Frame {
+            Array(Vec<LLSDValue>, std::vec::IntoIter<LLSDValue>),
+            Map(LLSDMap, <LLSDMap as IntoIterator>::IntoIter, String),
+        }
+        let mut stack: Vec<Frame> = Vec::new();
+        let mut current = self;
+        loop {
+            //  Descend into containers, transform leaves.
+            let mut result = match current {
+                LLSDValue::Array(v) => {
+                    stack.push(Frame::Array(Vec::with_capacity(v.len()), v.into_iter()));
+                    None
+                }
+                LLSDValue::Map(v) => {
+                    stack.push(Frame::Map(LLSDMap::new(), v.into_iter(), String::new()));
+                    None
+                }
+                leaf => Some(f(leaf)),
+            };
+            //  Ascend, finishing containers, until there is another child to descend into.
+            loop {
+                if let Some(v) = result.take() {
+                    match stack.last_mut() {
+                        None => return v, // finished the root
+                        Some(Frame::Array(done, _)) => done.push(v),
+                        Some(Frame::Map(done, _, key)) => {
+                            let _ = done.insert(std::mem::take(key), v);
+                        }
+                    }
+                }
+                let next = match stack.last_mut() {
+                    Some(Frame::Array(_, rest)) => rest.next(),
+                    Some(Frame::Map(_, rest, key)) => rest.next().map(|(k, v)| {
+                        *key = k;
+                        v
+                    }),
+                    None => unreachable!("map_recursive: empty stack"),
+                };
+                match next {
+                    Some(child) => {
+                        current = child;
+                        break;
+                    }
+                    None => {
+                        result = Some(f(match stack.pop() {
+                            Some(Frame::Array(done, _)) => LLSDValue::Array(done),
+                            Some(Frame::Map(done, _, _)) => LLSDValue::Map(done),
+                            None => unreachable!("map_recursive: empty stack"),
+                        }));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Convert to another LLSD type, if there's a sensible conversion.
+    /// Numbers, Booleans, and strings convert among themselves,
+    /// the way LSL and the SL viewer do it.
+    /// Returns None if the conversion is not possible.
+    pub fn coerce(&self, target: LLSDType) -> Option<LLSDValue> {
+        match (self, target) {
+            //  Booleans
+            (LLSDValue::Boolean(v), LLSDType::Boolean) => Some(LLSDValue::Boolean(*v)),
+            (LLSDValue::Integer(v), LLSDType::Boolean) => Some(LLSDValue::Boolean(*v != 0)),
+            (LLSDValue::Real(v), LLSDType::Boolean) => Some(LLSDValue::Boolean(*v != 0.0)),
+            (LLSDValue::String(v), LLSDType::Boolean) => match v.trim() {
+                "0" | "0.0" | "false" => Some(LLSDValue::Boolean(false)),
+                "1" | "1.0" | "true" => Some(LLSDValue::Boolean(true)),
+                _ => None,
+            },
+            //  Integers
+            (LLSDValue::Integer(v), LLSDType::Integer) => Some(LLSDValue::Integer(*v)),
+            (LLSDValue::Boolean(v), LLSDType::Integer) => Some(LLSDValue::Integer(*v as i32)),
+            (LLSDValue::Real(v), LLSDType::Integer) => {
+                //  Only if integral and in range. No silent truncation.
+                if v.fract() == 0.0 && *v >= i32::MIN as f64 && *v <= i32::MAX as f64 {
+                    Some(LLSDValue::Integer(*v as i32))
+                } else {
+                    None
+                }
+            }
+            (LLSDValue::String(v), LLSDType::Integer) => {
+                v.trim().parse::<i32>().ok().map(LLSDValue::Integer)
+            }
+            (LLSDValue::Date(v), LLSDType::Integer) => {
+                let secs = v.floor();
+                if secs >= i32::MIN as f64 && secs <= i32::MAX as f64 {
+                    Some(LLSDValue::Integer(secs as i32))
+                } else {
+                    None
+                }
+            }
+            //  Reals
+            (LLSDValue::Real(v), LLSDType::Real) => Some(LLSDValue::Real(*v)),
+            (LLSDValue::Integer(v), LLSDType::Real) => Some(LLSDValue::Real(*v as f64)),
+            (LLSDValue::Boolean(v), LLSDType::Real) => {
+                Some(LLSDValue::Real(if *v { 1.0 } else { 0.0 }))
+            }
+            (LLSDValue::String(v), LLSDType::Real) => {
+                v.trim().parse::<f64>().ok().map(LLSDValue::Real)
+            }
+            //  Strings
+            (LLSDValue::String(v), LLSDType::String) => Some(LLSDValue::String(v.clone())),
+            (LLSDValue::URI(v), LLSDType::String) => Some(LLSDValue::String(v.clone())),
+            (LLSDValue::Integer(v), LLSDType::String) => Some(LLSDValue::String(v.to_string())),
+            (LLSDValue::Real(v), LLSDType::String) => Some(LLSDValue::String(v.to_string())),
+            (LLSDValue::Boolean(v), LLSDType::String) => Some(LLSDValue::String(v.to_string())),
+            (LLSDValue::UUID(v), LLSDType::String) => Some(LLSDValue::String(v.to_string())),
+            (LLSDValue::Binary(v), LLSDType::String) => {
+                String::from_utf8(v.clone()).ok().map(LLSDValue::String)
+            }
+            //  Other scalars
+            (LLSDValue::UUID(v), LLSDType::UUID) => Some(LLSDValue::UUID(*v)),
+            (LLSDValue::String(v), LLSDType::UUID) => {
+                Uuid::parse_str(v.trim()).ok().map(LLSDValue::UUID)
+            }
+            (LLSDValue::URI(v), LLSDType::URI) => Some(LLSDValue::URI(v.clone())),
+            (LLSDValue::String(v), LLSDType::URI) => Some(LLSDValue::URI(v.clone())),
+            (LLSDValue::Date(v), LLSDType::Date) => Some(LLSDValue::Date(*v)),
+            (LLSDValue::Integer(v), LLSDType::Date) => Some(LLSDValue::Date(*v as f64)),
+            (LLSDValue::Binary(v), LLSDType::Binary) => Some(LLSDValue::Binary(v.clone())),
+            (LLSDValue::String(v), LLSDType::Binary) => {
+                Some(LLSDValue::Binary(v.as_bytes().to_vec()))
+            }
+            //  Containers and Undefined only convert to themselves.
+            (LLSDValue::Undefined, LLSDType::Undefined) => Some(LLSDValue::Undefined),
+            (LLSDValue::Map(_), LLSDType::Map) => Some(self.clone()),
+            (LLSDValue::Array(_), LLSDType::Array) => Some(self.clone()),
+            _ => None,
+        }
+    }
+}
+
+//  Conversions from Rust values, so trees can be built with .into().
+impl From<bool> for LLSDValue {
+    fn from(v: bool) -> Self {
+        LLSDValue::Boolean(v)
+    }
+}
+
+impl From<i32> for LLSDValue {
+    fn from(v: i32) -> Self {
+        LLSDValue::Integer(v)
+    }
+}
+
+impl From<f64> for LLSDValue {
+    fn from(v: f64) -> Self {
+        LLSDValue::Real(v)
+    }
+}
+
+impl From<&str> for LLSDValue {
+    fn from(v: &str) -> Self {
+        LLSDValue::String(v.to_string())
+    }
+}
+
+impl From<String> for LLSDValue {
+    fn from(v: String) -> Self {
+        LLSDValue::String(v)
+    }
+}
+
+impl From<Uuid> for LLSDValue {
+    fn from(v: Uuid) -> Self {
+        LLSDValue::UUID(v)
+    }
+}
+
+impl From<Vec<u8>> for LLSDValue {
+    fn from(v: Vec<u8>) -> Self {
+        LLSDValue::Binary(v)
+    }
+}
+
+/// Element type must be known; a bare `vec![...].into()` is ambiguous with `Vec<u8>`.
+impl From<Vec<LLSDValue>> for LLSDValue {
+    fn from(v: Vec<LLSDValue>) -> Self {
+        LLSDValue::Array(v)
+    }
+}
+
+/// Build a map from key/value pairs.
+impl FromIterator<(String, LLSDValue)> for LLSDValue {
+    fn from_iter<I: IntoIterator<Item = (String, LLSDValue)>>(iter: I) -> Self {
+        LLSDValue::Map(iter.into_iter().collect())
+    }
+}
+
+/// Push a container's children for a depth-first walk, so the first child pops first.
+fn push_children<'a>(stack: &mut Vec<&'a LLSDValue>, val: &'a LLSDValue) {
+    match val {
+        LLSDValue::Map(v) => {
+            let start = stack.len();
+            stack.extend(v.values());
+            stack[start..].reverse();
+        }
+        LLSDValue::Array(v) => stack.extend(v.iter().rev()),
+        _ => {}
+    }
+}
+
+/// Split a slash-delimited path into components.
+/// "" is the whole value. Otherwise the path must begin with "/".
+fn pointer_tokens(path: &str) -> Option<Vec<String>> {
+    if path.is_empty() {
+        return Some(Vec::new());
+    }
+    Some(
+        path.strip_prefix('/')?
+            .split('/')
+            .map(|t| t.replace("~1", "/").replace("~0", "~"))
+            .collect(),
+    )
+}
+
+/// Join path components back into a slash-delimited path.
+pub(crate) fn tokens_to_pointer(tokens: &[String]) -> String {
+    tokens
+        .iter()
+        .map(|t| format!("/{}", t.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+#[test]
+fn testcoerce() {
+    //  Conversions which should work.
+    assert_eq!(LLSDValue::Integer(3).coerce(LLSDType::Real), Some(LLSDValue::Real(3.0)));
+    assert_eq!(LLSDValue::Real(4.0).coerce(LLSDType::Integer), Some(LLSDValue::Integer(4)));
+    assert_eq!(LLSDValue::String(" 42 ".to_string()).coerce(LLSDType::Integer), Some(LLSDValue::Integer(42)));
+    assert_eq!(LLSDValue::Boolean(true).coerce(LLSDType::Integer), Some(LLSDValue::Integer(1)));
+    assert_eq!(LLSDValue::Integer(0).coerce(LLSDType::Boolean), Some(LLSDValue::Boolean(false)));
+    assert_eq!(LLSDValue::Real(1.5).coerce(LLSDType::String), Some(LLSDValue::String("1.5".to_string())));
+    assert_eq!(
+        LLSDValue::String("67153d5b-3659-afb4-8510-adda2c034649".to_string()).coerce(LLSDType::UUID),
+        Some(LLSDValue::UUID(Uuid::parse_str("67153d5b-3659-afb4-8510-adda2c034649").unwrap()))
+    );
+    //  Conversions which should fail.
+    assert_eq!(LLSDValue::Real(4.5).coerce(LLSDType::Integer), None);           // not integral
+    assert_eq!(LLSDValue::Real(1.0e12).coerce(LLSDType::Integer), None);        // out of range
+    assert_eq!(LLSDValue::String("abc".to_string()).coerce(LLSDType::Integer), None);
+    assert_eq!(LLSDValue::Undefined.coerce(LLSDType::Integer), None);
+    assert_eq!(LLSDValue::Array(vec![]).coerce(LLSDType::Map), None);
+    assert_eq!(LLSDValue::Integer(1).coerce(LLSDType::Array), None);
+}
+
+#[test]
+fn testnewcontainers() {
+    let m = LLSDValue::new_map();
+    assert!(m.as_map().unwrap().is_empty());
+    let a = LLSDValue::new_array();
+    assert!(a.as_array().unwrap().is_empty());
+}
+
+#[test]
+fn testniluuid() {
+    let id = Uuid::parse_str("67153d5b-3659-afb4-8510-adda2c034649").unwrap();
+    assert!(!LLSDValue::UUID(id).is_nil_uuid());
+    assert_eq!(LLSDValue::UUID(id).as_uuid_or_nil(), id);
+    assert!(LLSDValue::UUID(Uuid::nil()).is_nil_uuid());
+    assert!(!LLSDValue::Undefined.is_nil_uuid());
+    assert_eq!(LLSDValue::Undefined.as_uuid_or_nil(), Uuid::nil());
+    assert_eq!(LLSDValue::Integer(0).as_uuid_or_nil(), Uuid::nil());
+    //  An empty <uuid/> in XML is the nil UUID.
+    let parsed = from_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?><llsd><uuid /></llsd>").unwrap();
+    assert!(parsed.is_nil_uuid());
+}
+
+#[test]
+fn testmerge() {
+    //  Nested arrays inside a map.
+    let old = notation_from_str("{'a':[i1,[i2,i3]],'b':i4}").unwrap();
+    let new = notation_from_str("{'a':[i10,[i20]],'c':i5}").unwrap();
+    //  Replace, the default.
+    let mut merged = old.clone();
+    merged.merge(new.clone());
+    assert_eq!(merged, notation_from_str("{'a':[i10,[i20]],'b':i4,'c':i5}").unwrap());
+    //  By index, recursively.
+    let mut merged = old.clone();
+    merged.merge_with_options(new, &MergeOptions { array_strategy: ArrayMerge::Index });
+    assert_eq!(merged, notation_from_str("{'a':[i10,[i20,i3]],'b':i4,'c':i5}").unwrap());
+    //  Longer new array is appended.
+    let mut merged = notation_from_str("[i1]").unwrap();
+    merged.merge_with_options(notation_from_str("[i7,i8]").unwrap(), &MergeOptions { array_strategy: ArrayMerge::Index });
+    assert_eq!(merged, notation_from_str("[i7,i8]").unwrap());
+}
+
+#[test]
+fn testsortkeysrecursive() {
+    //  Maps are either always sorted or have no order, so the value is unchanged.
+    let mut val = LLSDValue::new_map()
+        .with_key(
+            "z",
+            LLSDValue::new_map().with_key("b", LLSDValue::Integer(1)).with_key("a", LLSDValue::Integer(2)),
+        )
+        .with_key("m", LLSDValue::Integer(3))
+        .with_key("a", LLSDValue::Integer(4));
+    let before = val.clone();
+    val.sort_keys_recursive();
+    assert_eq!(val, before);
+}
+
+#[test]
+#[cfg(feature = "ordered-map")]
+fn testsortkeysrecursive2() {
+    //  With ordered maps, keys come out sorted at every level.
+    let mut val = LLSDValue::new_map()
+        .with_key(
+            "z",
+            LLSDValue::new_map().with_key("b", LLSDValue::Integer(1)).with_key("a", LLSDValue::Integer(2)),
+        )
+        .with_key("m", LLSDValue::Integer(3))
+        .with_key("a", LLSDValue::Integer(4));
+    val.sort_keys_recursive();
+    assert_eq!(val.as_map().unwrap().keys().collect::<Vec<_>>(), ["a", "m", "z"]);
+    let s = crate::notation_to_string(&val).unwrap();
+    assert_eq!(s, "<? llsd/notation ?>\n{'a':i4,\n'm':i3,\n'z':{'a':i2,\n'b':i1}}");
+}
+
+#[test]
+fn testmaprecursive() {
+    //  Replace every Binary with Undefined, at all levels.
+    let tree = notation_from_bytes(b"{'a':b64\"AAEC\",'b':[i1,b16\"0fa1\",{'c':b64\"AAEC\"}],'d':[]}").unwrap();
+    let mapped = tree.map_recursive(|v| match v {
+        LLSDValue::Binary(_) => LLSDValue::Undefined,
+        other => other,
+    });
+    assert_eq!(mapped, notation_from_str("{'a':!,'b':[i1,!,{'c':!}],'d':[]}").unwrap());
+    //  Parents see transformed children.
+    let tree = notation_from_str("[[i1,i2],i3]").unwrap();
+    let summed = tree.map_recursive(|v| match v {
+        LLSDValue::Array(items) => LLSDValue::Integer(items.iter().map(|i| *i.as_integer().unwrap()).sum()),
+        other => other,
+    });
+    assert_eq!(summed, LLSDValue::Integer(6));
+    //  Scalar root.
+    assert_eq!(LLSDValue::Integer(1).map_recursive(|_| LLSDValue::Boolean(true)), LLSDValue::Boolean(true));
+}
+
+#[test]
+fn testcontainerlen() {
+    let a = notation_from_str("[i1,i2,i3]").unwrap();
+    assert_eq!(a.array_len(), Some(3));
+    assert_eq!(a.map_len(), None);
+    let m = notation_from_str("{'a':i1}").unwrap();
+    assert_eq!(m.map_len(), Some(1));
+    assert_eq!(m.array_len(), None);
+    let v = LLSDValue::String("abc".to_string());
+    assert_eq!(v.array_len(), None);
+    assert_eq!(v.map_len(), None);
+}
+
+#[test]
+fn testdepth() {
+    assert_eq!(LLSDValue::Integer(1).depth(), 0);
+    assert_eq!(notation_from_str("[i1,i2]").unwrap().depth(), 1);
+    assert_eq!(notation_from_str("[]").unwrap().depth(), 1);
+    assert_eq!(notation_from_str("{'a':i1,'b':{'c':{'d':i2}},'e':[]}").unwrap().depth(), 3);
+}
+
+#[test]
+fn testasnumber() {
+    assert_eq!(LLSDValue::Integer(3).as_number(), Some(Number::Int(3)));
+    assert_eq!(LLSDValue::Real(3.0).as_number(), Some(Number::Real(3.0)));
+    assert_eq!(LLSDValue::String("3".to_string()).as_number(), None);
+    assert_eq!(LLSDValue::Boolean(true).as_number(), None);
+}
+
+#[test]
+fn testapplypatch() {
+    let mut val = notation_from_str("{'agent':{'name':'Phoenix','session_id':u2c585cec-038c-40b0-b42e-a25ebab4d132},'pos':[r1,r2]}").unwrap();
+    val.apply_patch(&[
+        PatchOp::Set { path: "/agent/name".to_string(), value: LLSDValue::String("Linden".to_string()) },
+        PatchOp::Set { path: "/pos/2".to_string(), value: LLSDValue::Real(3.0) },
+        PatchOp::Set { path: "/a~1b".to_string(), value: LLSDValue::Integer(1) },
+        PatchOp::Remove { path: "/agent/session_id".to_string() },
+        PatchOp::Remove { path: "/pos/0".to_string() },
+    ])
+    .unwrap();
+    assert_eq!(val, notation_from_str("{'agent':{'name':'Linden'},'pos':[r2,r3],'a/b':i1}").unwrap());
+    assert_eq!(val.pointer_mut("/pos/1"), Some(&mut LLSDValue::Real(3.0)));
+    //  Errors
+    assert!(val.apply_patch(&[PatchOp::Remove { path: "/agent/missing".to_string() }]).is_err());
+    assert!(val.apply_patch(&[PatchOp::Remove { path: "/pos/5".to_string() }]).is_err());
+    assert!(val.apply_patch(&[PatchOp::Set { path: "/nothere/x".to_string(), value: LLSDValue::Undefined }]).is_err());
+    assert!(val.apply_patch(&[PatchOp::Set { path: "no slash".to_string(), value: LLSDValue::Undefined }]).is_err());
+    //  Whole value
+    val.apply_patch(&[PatchOp::Set { path: "".to_string(), value: LLSDValue::Integer(0) }]).unwrap();
+    assert_eq!(val, LLSDValue::Integer(0));
+}
+
+#[test]
+fn testdatefromrfc3339() {
+    assert_eq!(LLSDValue::date_from_rfc3339("2006-02-01T14:29:53Z").unwrap(), LLSDValue::Date(1138804193.0));
+    assert_eq!(LLSDValue::date_from_rfc3339("2006-02-01T16:29:53+02:00").unwrap(), LLSDValue::Date(1138804193.0));
+    assert!(LLSDValue::date_from_rfc3339("February 1, 2006").is_err());
+}
+
+#[test]
+fn testasstrlossy() {
+    let id = Uuid::parse_str("67153d5b-3659-afb4-8510-adda2c034649").unwrap();
+    assert_eq!(LLSDValue::Undefined.as_str_lossy(), Some("".to_string()));
+    assert_eq!(LLSDValue::Boolean(true).as_str_lossy(), Some("true".to_string()));
+    assert_eq!(LLSDValue::Real(1.5).as_str_lossy(), Some("1.5".to_string()));
+    assert_eq!(LLSDValue::Integer(-3).as_str_lossy(), Some("-3".to_string()));
+    assert_eq!(LLSDValue::UUID(id).as_str_lossy(), Some("67153d5b-3659-afb4-8510-adda2c034649".to_string()));
+    assert_eq!(LLSDValue::String("abc".to_string()).as_str_lossy(), Some("abc".to_string()));
+    assert_eq!(LLSDValue::URI("http://example.com".to_string()).as_str_lossy(), Some("http://example.com".to_string()));
+    assert_eq!(LLSDValue::Date(1138804193.0).as_str_lossy(), Some("2006-02-01T14:29:53Z".to_string()));
+    assert_eq!(LLSDValue::Binary(b"Hello world".to_vec()).as_str_lossy(), Some("SGVsbG8gd29ybGQ=".to_string()));
+    assert_eq!(LLSDValue::new_map().as_str_lossy(), None);
+    assert_eq!(LLSDValue::new_array().as_str_lossy(), None);
+}
+
+#[test]
+fn testrenamekey() {
+    let mut val = notation_from_str("{'old':i1,'other':i2,'nested':[{'old':i3}]}").unwrap();
+    assert!(val.clone().rename_key("old", "new"));
+    assert!(!val.clone().rename_key("missing", "new"));
+    assert!(!LLSDValue::Integer(1).rename_key("old", "new"));
+    assert_eq!(val.rename_key_recursive("old", "new"), 2);
+    assert_eq!(val, notation_from_str("{'new':i1,'other':i2,'nested':[{'new':i3}]}").unwrap());
+}
+
+#[test]
+fn testgetor() {
+    let config = notation_from_str("{'timeout':i30}").unwrap();
+    let default = LLSDValue::Integer(10);
+    assert_eq!(config.get_or("timeout", &default), &LLSDValue::Integer(30));
+    assert_eq!(config.get_or("retries", &default), &default);
+    assert_eq!(LLSDValue::Undefined.get_or("timeout", &default), &default);
+}
+
+#[test]
+fn testtryinto() {
+    let m = notation_from_str("{'a':i1}").unwrap().try_into_map().unwrap();
+    assert_eq!(m["a"], LLSDValue::Integer(1));
+    let a = notation_from_str("[i1,i2]").unwrap().try_into_array().unwrap();
+    assert_eq!(a.len(), 2);
+    let err = LLSDValue::Integer(1).try_into_map().unwrap_err();
+    assert_eq!(err.to_string(), "Expected LLSD map, found integer");
+    let err = LLSDValue::new_map().try_into_array().unwrap_err();
+    assert_eq!(err.to_string(), "Expected LLSD array, found map");
+}
+
+#[test]
+fn testreplacenonfinite() {
+    let mut val = LLSDValue::Array(vec![
+        LLSDValue::Real(f64::NAN),
+        LLSDValue::Array(vec![LLSDValue::Real(f64::INFINITY), LLSDValue::Real(1.5)]),
+        LLSDValue::Map([("x".to_string(), LLSDValue::Real(f64::NAN))].into_iter().collect()),
+    ]);
+    assert_eq!(val.replace_nonfinite(LLSDValue::Undefined), 3);
+    assert_eq!(val, crate::notation_from_str("[!,[!,r1.5],{'x':!}]").unwrap());
+}
+
+#[test]
+fn testequivalentignoringundefined() {
+    let a = notation_from_str("{'a':i1,'b':!,'c':[{'d':!}]}").unwrap();
+    let b = notation_from_str("{'a':i1,'c':[{}]}").unwrap();
+    assert_ne!(a, b);
+    assert!(a.equivalent_ignoring_undefined(&b));
+    assert!(b.equivalent_ignoring_undefined(&a));
+    //  Real differences still count.
+    let c = notation_from_str("{'a':i2,'b':!}").unwrap();
+    assert!(!a.equivalent_ignoring_undefined(&c));
+    let d = notation_from_str("{'a':i1,'b':i0,'c':[{}]}").unwrap();
+    assert!(!a.equivalent_ignoring_undefined(&d));
+    //  Array elements are not optional.
+    let e = notation_from_str("[i1,!]").unwrap();
+    let f = notation_from_str("[i1]").unwrap();
+    assert!(!e.equivalent_ignoring_undefined(&f));
+}
+
+#[test]
+fn testbase64() {
+    let val = LLSDValue::binary_from_base64("SGVsbG8g\nd29ybGQ=\n").unwrap();
+    assert_eq!(val, LLSDValue::Binary(b"Hello world".to_vec()));
+    assert_eq!(val.binary_as_base64().unwrap(), "SGVsbG8gd29ybGQ=");
+    let val = LLSDValue::Binary((0..=255).collect());
+    assert_eq!(LLSDValue::binary_from_base64(&val.binary_as_base64().unwrap()).unwrap(), val);
+    assert!(LLSDValue::binary_from_base64("not base64!").is_err());
+    assert!(LLSDValue::String("SGVsbG8=".to_string()).binary_as_base64().is_none());
+}
+
+#[test]
+fn testresizearray() {
+    let mut val = notation_from_str("[r1,r2]").unwrap();
+    val.resize_array(3, LLSDValue::Real(0.0)).unwrap();
+    assert_eq!(val, notation_from_str("[r1,r2,r0]").unwrap());
+    val.resize_array(1, LLSDValue::Real(0.0)).unwrap();
+    assert_eq!(val, notation_from_str("[r1]").unwrap());
+    let mut val = LLSDValue::Integer(1);
+    assert!(val.resize_array(3, LLSDValue::Undefined).is_err());
+    assert_eq!(val, LLSDValue::Integer(1));
+}
+
+#[test]
+fn testbinaryasstring() {
+    let val = LLSDValue::Binary(b"Hello world".to_vec());
+    assert_eq!(val.binary_as_string(), Some("Hello world"));
+    assert_eq!(LLSDValue::Binary(vec![0xff, 0xfe]).binary_as_string(), None);
+    assert_eq!(LLSDValue::String("Hello".to_string()).binary_as_string(), None);
+    //  Binary that happens to be UTF-8 stays Binary through the text formats.
+    let s = crate::notation_to_string(&val).unwrap();
+    assert_eq!(notation_from_str(&s).unwrap(), val);
+    let s = crate::to_string(&val, false).unwrap();
+    assert_eq!(crate::from_str(&s).unwrap(), val);
+}
+
+#[test]
+fn testtypehistogram() {
+    let val = notation_from_str("[i1,i2,{'a':r1.5,'b':[i3,'x',!]},'y']").unwrap();
+    let counts = val.type_histogram();
+    assert_eq!(counts["integer"], 3);
+    assert_eq!(counts["array"], 2);
+    assert_eq!(counts["map"], 1);
+    assert_eq!(counts["real"], 1);
+    assert_eq!(counts["string"], 2);
+    assert_eq!(counts["undef"], 1);
+    assert_eq!(counts.len(), 6);
+    assert_eq!(LLSDValue::example().type_histogram().len(), 11);
+}
+
+#[test]
+fn testwithkey() {
+    let built = LLSDValue::new_map()
+        .with_key("x", LLSDValue::Integer(1))
+        .with_key("y".to_string(), LLSDValue::new_array().with_element(LLSDValue::Real(2.0)))
+        .with_key("x", LLSDValue::Integer(3));
+    let mut manual = LLSDMap::new();
+    manual.insert("x".to_string(), LLSDValue::Integer(3));
+    manual.insert("y".to_string(), LLSDValue::Array(vec![LLSDValue::Real(2.0)]));
+    assert_eq!(built, LLSDValue::Map(manual));
+    //  Non-containers become empty containers first.
+    assert_eq!(LLSDValue::Undefined.with_element(LLSDValue::Integer(1)), notation_from_str("[i1]").unwrap());
+    assert_eq!(LLSDValue::new_array().with_key("a", LLSDValue::Undefined), notation_from_str("{'a':!}").unwrap());
+}
+
+#[test]
+fn testredact() {
+    let mut val = crate::notation_from_str(
+        "[{'version':i1},{'session_id':u2c585cec-038c-40b0-b42e-a25ebab4d132,'first_name':'Phoenix',\
+         'attachment_data':[{'session_id':'nested','item_id':i2}],'login':{'password':'secret'}}]",
+    )
+    .unwrap();
+    assert_eq!(val.redact(&["session_id", "password"]), 3);
+    let redacted = LLSDValue::String("***".to_string());
+    let mut at = |path: &str| val.pointer_mut(path).cloned();
+    assert_eq!(at("/1/session_id"), Some(redacted.clone()));
+    assert_eq!(at("/1/attachment_data/0/session_id"), Some(redacted.clone()));
+    assert_eq!(at("/1/login/password"), Some(redacted.clone()));
+    //  Everything else untouched.
+    assert_eq!(at("/1/first_name"), Some(LLSDValue::String("Phoenix".to_string())));
+    assert_eq!(at("/1/attachment_data/0/item_id"), Some(LLSDValue::Integer(2)));
+    assert_eq!(at("/0/version"), Some(LLSDValue::Integer(1)));
+}
+
+#[test]
+fn testpathexists() {
+    let login = crate::notation_from_str(
+        "[{'destination':l\"http://secondlife.com\"},{'agent_id':u3c115e51-04f4-523c-9fa6-98aff1034730,\
+         'position':[r70.9247,r254.378,r38.7304],'attachment_data':[{'attachment_point':i2}],'a/b':i1}]",
+    )
+    .unwrap();
+    assert!(login.path_exists(""));
+    assert!(login.path_exists("/1/agent_id"));
+    assert!(login.path_exists("/1/position/2"));
+    assert!(login.path_exists("/1/attachment_data/0/attachment_point"));
+    assert!(login.path_exists("/1/a~1b"));
+    assert!(!login.path_exists("/agent_id")); // top level is an array
+    assert!(!login.path_exists("/1/position/3"));
+    assert!(!login.path_exists("/1/agent_id/0")); // not a container
+    assert!(!login.path_exists("/0/missing"));
+    assert!(!login.path_exists("no_leading_slash"));
+    assert_eq!(login.pointer("/1/position/0"), Some(&LLSDValue::Real(70.9247)));
+}
+
+#[test]
+fn testgetindex() {
+    let val = crate::notation_from_str("{'overrides':[{'local_id':i42,'te':i3}],'name':'mat'}").unwrap();
+    let first = val.get("overrides").and_then(|v| v.index(0)).unwrap();
+    assert_eq!(first.get("local_id").and_then(|v| v.as_integer()), Some(&42));
+    assert_eq!(val.pointer("/overrides/0/te"), first.get("te"));
+    //  Mismatches are None, not panics.
+    assert_eq!(val.get("missing"), None);
+    assert_eq!(val.index(0), None); // map, not array
+    assert_eq!(val.get("overrides").unwrap().get("local_id"), None); // array, not map
+    assert_eq!(val.get("overrides").unwrap().index(1), None);
+    assert_eq!(val.get("name").unwrap().get("x"), None);
+}
+
+#[test]
+fn testuuidlenient() {
+    let id = Uuid::parse_str("3c115e51-04f4-523c-9fa6-98aff1034730").unwrap();
+    assert_eq!(LLSDValue::UUID(id).as_uuid_lenient(), Some(id));
+    assert_eq!(LLSDValue::String("3c115e51-04f4-523c-9fa6-98aff1034730".to_string()).as_uuid_lenient(), Some(id));
+    assert_eq!(LLSDValue::URI(" 3C115E51-04F4-523C-9FA6-98AFF1034730\n".to_string()).as_uuid_lenient(), Some(id));
+    assert_eq!(LLSDValue::String("not a uuid".to_string()).as_uuid_lenient(), None);
+    assert_eq!(LLSDValue::String(String::new()).as_uuid_lenient(), None);
+    assert_eq!(LLSDValue::Integer(1).as_uuid_lenient(), None);
+}
+
+#[test]
+fn testreal() {
+    assert_eq!(LLSDValue::real(1.5).unwrap(), LLSDValue::Real(1.5));
+    assert_eq!(LLSDValue::real(-0.0).unwrap(), LLSDValue::Real(-0.0));
+    assert!(LLSDValue::real(f64::NAN).is_err());
+    assert!(LLSDValue::real(f64::INFINITY).is_err());
+    assert!(LLSDValue::real(f64::NEG_INFINITY).is_err());
+    assert_eq!(LLSDValue::real_or_zero(1.5), LLSDValue::Real(1.5));
+    assert_eq!(LLSDValue::real_or_zero(f64::NAN), LLSDValue::Real(0.0));
+    assert_eq!(LLSDValue::real_or_zero(f64::INFINITY), LLSDValue::Real(0.0));
+    assert_eq!(LLSDValue::real_or_zero(f64::NEG_INFINITY), LLSDValue::Real(0.0));
+}
+
+#[test]
+fn testbinarystringlen() {
+    assert_eq!(LLSDValue::Binary(vec![1, 2, 3]).binary_len(), Some(3));
+    assert_eq!(LLSDValue::Binary(Vec::new()).binary_len(), Some(0));
+    assert_eq!(LLSDValue::String("héllo".to_string()).string_len(), Some(6)); // bytes, not chars
+    assert_eq!(LLSDValue::URI("http://a".to_string()).string_len(), Some(8));
+    assert_eq!(LLSDValue::String("abc".to_string()).binary_len(), None);
+    assert_eq!(LLSDValue::Binary(vec![1]).string_len(), None);
+    assert_eq!(LLSDValue::Array(vec![LLSDValue::Undefined]).string_len(), None);
+}
+
+#[test]
+fn testarraycoerced() {
+    let one = crate::notation_from_str("{'sides':i0}").unwrap();
+    let many = crate::notation_from_str("{'sides':[i0,i3]}").unwrap();
+    let sides = |v: &LLSDValue| -> Vec<i32> {
+        v.get("sides").unwrap().as_array_coerced().iter().map(|s| *s.as_integer().unwrap()).collect()
+    };
+    assert_eq!(sides(&one), vec![0]);
+    assert_eq!(sides(&many), vec![0, 3]);
+    assert!(LLSDValue::Array(Vec::new()).as_array_coerced().is_empty());
+    let map = LLSDValue::new_map();
+    assert_eq!(map.as_array_coerced(), vec![&map]);
+}
+
+#[test]
+fn testfrom() {
+    let items: Vec<LLSDValue> = vec![1.into(), "hi".into()];
+    let val: LLSDValue = items.into();
+    assert_eq!(val, LLSDValue::Array(vec![LLSDValue::Integer(1), LLSDValue::String("hi".to_string())]));
+    assert_eq!(LLSDValue::from(true), LLSDValue::Boolean(true));
+    assert_eq!(LLSDValue::from(0.5), LLSDValue::Real(0.5));
+    assert_eq!(LLSDValue::from("x".to_string()), LLSDValue::String("x".to_string()));
+    assert_eq!(LLSDValue::from(Uuid::nil()), LLSDValue::UUID(Uuid::nil()));
+    assert_eq!(LLSDValue::from(vec![1u8, 2]), LLSDValue::Binary(vec![1, 2]));
+    let map: LLSDValue = [("a".to_string(), 1.into()), ("b".to_string(), "two".into())].into_iter().collect();
+    assert_eq!(map, LLSDValue::new_map().with_key("a", 1).with_key("b", "two"));
+    assert_eq!(map.get("b"), Some(&LLSDValue::String("two".to_string())));
+}
@@ -12,7 +12,7 @@
 //  License: LGPL.
 //
 use crate::LLSDValue;
-use anyhow::Error;
+use anyhow::{anyhow, Error};
 use std::io::Write;
 //
 //  Constants
@@ -28,6 +28,14 @@ pub fn to_bytes(val: &LLSDValue) -> Result<Vec<u8>, Error> {
     Ok(writer)
 }
 
+/// Outputs an LLSDValue in LLSD "binary" format into a caller-provided buffer.
+/// The buffer is cleared first. Reusing one buffer across many
+/// messages avoids an allocation per message.
+pub fn to_buf(buf: &mut Vec<u8>, val: &LLSDValue) -> Result<(), Error> {
+    buf.clear();
+    to_writer(buf, val)
+}
+
 /// Outputs an LLSD value to an output stream
 pub fn to_writer<W: Write>(writer: &mut W, val: &LLSDValue) -> Result<(), Error> {
     writer.write_all(LLSDBINARYPREFIX)?; // prefix
@@ -36,6 +44,68 @@ pub fn to_writer<W: Write>(writer: &mut W, val: &LLSDValue) -> Result<(), Error>
     Ok(())
 }
 
+/// Outputs an LLSDValue in LLSD "binary" format, as one length-prefixed frame.
+/// The frame is a big-endian u32 byte count, then that many bytes of
+/// binary LLSD. The count includes the binary LLSD header.
+/// Read with de::binary::from_length_prefixed.
+pub fn to_length_prefixed(val: &LLSDValue) -> Result<Vec<u8>, Error> {
+    let mut writer: Vec<u8> = vec![0; 4]; // length goes here
+    to_writer(&mut writer, val)?;
+    let len = checked_len(writer.len() - 4)?;
+    writer[0..4].copy_from_slice(&len.to_be_bytes());
+    Ok(writer)
+}
+
+/// Streams a top-level binary LLSD array to a writer that cannot seek, such as a socket.
+/// The binary format puts the element count before the elements, so the elements
+/// are buffered, already serialized, until finish. That is much smaller than holding
+/// the elements as LLSDValue trees, and each element can be dropped once pushed.
+pub struct ArrayWriter<W: Write> {
+    /// Final destination
+    writer: W,
+    /// Serialized elements so far
+    buf: Vec<u8>,
+    /// Number of elements so far
+    count: usize,
+}
+
+impl<W: Write> ArrayWriter<W> {
+    /// New array writer. Nothing is written until finish.
+    pub fn new(writer: W) -> Self {
+        ArrayWriter { writer, buf: Vec::new(), count: 0 }
+    }
+
+    /// Add one element to the array.
+    pub fn push(&mut self, val: &LLSDValue) -> Result<(), Error> {
+        generate_value(&mut self.buf, val)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Write the whole array, with header, and return the writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.writer.write_all(LLSDBINARYPREFIX)?;
+        self.writer.write_all(b"[")?;
+        write_len(&mut self.writer, self.count)?;
+        self.writer.write_all(&self.buf)?;
+        self.writer.write_all(b"]")?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Length or count as u32, the binary format's limit.
+/// Error rather than truncating and producing a corrupt stream.
+fn checked_len(len: usize) -> Result<u32, Error> {
+    u32::try_from(len).map_err(|_| anyhow!("Length {} too large for binary LLSD, limit is {}", len, u32::MAX))
+}
+
+/// Write a length or count.
+fn write_len<W: Write>(writer: &mut W, len: usize) -> Result<(), Error> {
+    writer.write_all(&checked_len(len)?.to_be_bytes())?;
+    Ok(())
+}
+
 /// Generate one <TYPE> VALUE </TYPE> output. VALUE is recursive.
 fn generate_value<W: Write>(writer: &mut W, val: &LLSDValue) -> Result<(), Error> {
     //  Emit binary for all possible types.
@@ -44,12 +114,12 @@ fn generate_value<W: Write>(writer: &mut W, val: &LLSDValue) -> Result<(), Error
         LLSDValue::Boolean(v) => writer.write_all(if *v { b"1" } else { b"0" })?,
         LLSDValue::String(v) => {
             writer.write_all(b"s")?;
-            writer.write_all(&(v.len() as u32).to_be_bytes())?;
+            write_len(writer, v.len())?;
             writer.write_all(v.as_bytes())?
         }
         LLSDValue::URI(v) => {
             writer.write_all(b"l")?;
-            writer.write_all(&(v.len() as u32).to_be_bytes())?;
+            write_len(writer, v.len())?;
             writer.write_all(v.as_bytes())?
         }
         LLSDValue::Integer(v) => {
@@ -66,23 +136,24 @@ fn generate_value<W: Write>(writer: &mut W, val: &LLSDValue) -> Result<(), Error
         }
         LLSDValue::Binary(v) => {
             writer.write_all(b"b")?;
-            writer.write_all(&(v.len() as u32).to_be_bytes())?;
+            write_len(writer, v.len())?;
             writer.write_all(v)?
         }
         LLSDValue::Date(v) => {
+            //  Little-endian double, unlike reals. See de::binary.
             writer.write_all(b"d")?;
-            writer.write_all(&v.to_be_bytes())?
+            writer.write_all(&v.to_le_bytes())?
         }
 
         //  Map is { childcnt key value key value ... }
         LLSDValue::Map(v) => {
             //  Output count of key/value pairs
             writer.write_all(b"{")?;
-            writer.write_all(&(v.len() as u32).to_be_bytes())?;
+            write_len(writer, v.len())?;
             //  Output key/value pairs
             for (key, value) in v {
-                writer.write_all(&[b'k'])?; // k prefix to key. UNDOCUMENTED
-                writer.write_all(&(key.len() as u32).to_be_bytes())?;
+                writer.write_all(b"k")?; // k prefix to key. UNDOCUMENTED
+                write_len(writer, key.len())?;
                 writer.write_all(key.as_bytes())?;
                 generate_value(writer, value)?;
             }
@@ -92,7 +163,7 @@ fn generate_value<W: Write>(writer: &mut W, val: &LLSDValue) -> Result<(), Error
         LLSDValue::Array(v) => {
             //  Output count of array entries
             writer.write_all(b"[")?;
-            writer.write_all(&(v.len() as u32).to_be_bytes())?;
+            write_len(writer, v.len())?;
             //  Output array entries
             for value in v {
                 generate_value(writer, value)?;
@@ -102,3 +173,105 @@ fn generate_value<W: Write>(writer: &mut W, val: &LLSDValue) -> Result<(), Error
     };
     Ok(())
 }
+
+#[test]
+fn binarygentobuftest1() {
+    //  Serialize two values into the same buffer.
+    let mut buf = Vec::new();
+    let val1 = LLSDValue::Array(vec![LLSDValue::Integer(1), LLSDValue::String("first".to_string())]);
+    let val2 = LLSDValue::Integer(2);
+    to_buf(&mut buf, &val1).unwrap();
+    assert_eq!(buf, to_bytes(&val1).unwrap());
+    assert_eq!(crate::de::auto_from_bytes(&buf).unwrap(), val1);
+    let capacity = buf.capacity();
+    to_buf(&mut buf, &val2).unwrap();
+    assert_eq!(buf.capacity(), capacity); // no reallocation for a smaller value
+    assert_eq!(crate::de::auto_from_bytes(&buf).unwrap(), val2);
+}
+
+#[test]
+fn binarygenlentest1() {
+    assert_eq!(checked_len(5).unwrap(), 5);
+    assert_eq!(checked_len(u32::MAX as usize).unwrap(), u32::MAX);
+    #[cfg(target_pointer_width = "64")]
+    assert!(checked_len(u32::MAX as usize + 1).is_err());
+}
+
+#[test]
+fn binarygenarraywritertest1() {
+    //  A writer with no Seek.
+    struct NoSeek(Vec<u8>);
+    impl Write for NoSeek {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    const ITEMS: i32 = 10000;
+    let mut array_writer = ArrayWriter::new(NoSeek(Vec::new()));
+    for n in 0..ITEMS {
+        let item = LLSDValue::Map([("n".to_string(), LLSDValue::Integer(n))].into_iter().collect());
+        array_writer.push(&item).unwrap();
+    }
+    let written = array_writer.finish().unwrap().0;
+    let parsed = crate::de::auto_from_bytes(&written).unwrap();
+    let arr = parsed.as_array().unwrap();
+    assert_eq!(arr.len(), ITEMS as usize);
+    assert_eq!(arr[1234].as_map().unwrap()["n"], LLSDValue::Integer(1234));
+    //  Same bytes as serializing the whole array at once.
+    assert_eq!(written, to_bytes(&parsed).unwrap());
+}
+
+#[test]
+fn binarygenlengthprefixedtest1() {
+    let vals = [
+        LLSDValue::Integer(42),
+        LLSDValue::Array(vec![LLSDValue::String("Hello world".to_string()), LLSDValue::Real(1.5)]),
+    ];
+    //  Several frames back to back on one stream.
+    let mut stream: Vec<u8> = Vec::new();
+    for val in &vals {
+        let frame = to_length_prefixed(val).unwrap();
+        assert_eq!(u32::from_be_bytes(frame[0..4].try_into().unwrap()) as usize, frame.len() - 4);
+        assert!(frame[4..].starts_with(LLSDBINARYPREFIX));
+        stream.extend_from_slice(&frame);
+    }
+    let mut cursor = std::io::Cursor::new(stream);
+    for val in &vals {
+        assert_eq!(&crate::de::binary::from_length_prefixed(&mut cursor).unwrap(), val);
+    }
+}
+
+#[test]
+fn binarygenstringtest1() {
+    //  Strings must use the 's' type code, top level and inside a map.
+    let val = LLSDValue::String("Hello".to_string());
+    let b = to_bytes(&val).unwrap();
+    assert_eq!(&b[LLSDBINARYPREFIX.len()..], b"s\0\0\0\x05Hello");
+    assert_eq!(crate::de::binary::from_bytes(&b[LLSDBINARYPREFIX.len()..]).unwrap(), val);
+    let val = LLSDValue::Map([("greeting".to_string(), LLSDValue::String("Hello world".to_string()))].into_iter().collect());
+    let b = to_bytes(&val).unwrap();
+    assert_eq!(crate::de::binary::from_bytes(&b[LLSDBINARYPREFIX.len()..]).unwrap(), val);
+}
+
+#[test]
+fn binarygenshortwritetest1() {
+    //  A writer that accepts at most 3 bytes per call, like a non-blocking socket.
+    struct ShortWriter(Vec<u8>);
+    impl Write for ShortWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(3);
+            self.0.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let val = LLSDValue::example();
+    let mut w = ShortWriter(Vec::new());
+    to_writer(&mut w, &val).unwrap();
+    assert_eq!(w.0, to_bytes(&val).unwrap());
+}
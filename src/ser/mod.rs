@@ -2,3 +2,290 @@
 pub mod binary;
 pub mod xml;
 pub mod notation;
+pub mod serde_impl;
+
+use crate::{Format, LLSDMap, LLSDValue, PatchOp};
+use anyhow::{anyhow, Error};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// How the text serializers format reals.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RealFormat {
+    /// Shortest form that round-trips exactly.
+    #[default]
+    Shortest,
+    /// Fixed number of decimal places.
+    Fixed(usize),
+    /// Scientific notation, shortest mantissa.
+    Scientific,
+}
+
+impl RealFormat {
+    /// Format one real. Non-finite values come out as Rust prints them.
+    pub fn format(&self, v: f64) -> String {
+        match self {
+            RealFormat::Shortest => v.to_string(),
+            RealFormat::Fixed(places) => format!("{:.*}", places, v),
+            RealFormat::Scientific => format!("{:e}", v),
+        }
+    }
+}
+
+/// Earliest and latest dates RFC 3339 can express: years 0000 through 9999.
+const RFC3339_MIN_SECS: i64 = -62167219200; // 0000-01-01T00:00:00Z
+const RFC3339_MAX_SECS: i64 = 253402300799; // 9999-12-31T23:59:59Z
+
+/// Format seconds since the UNIX epoch as an RFC 3339 date, in UTC.
+/// Fractional seconds are emitted only if present, to the millisecond or finer.
+/// None if out of range.
+pub(crate) fn format_rfc3339_date(secs: f64) -> Option<String> {
+    use chrono::TimeZone;
+    if !secs.is_finite() {
+        return None;
+    }
+    //  Round to whole microseconds. An f64 has no more precision than that for
+    //  current dates, and this avoids emitting noise such as .122999906 for .123.
+    let micros = (secs * 1.0e6).round();
+    let whole = (micros / 1.0e6).floor();
+    let nanos = (micros - whole * 1.0e6) as u32 * 1000;
+    chrono::Utc
+        .timestamp_opt(whole as i64, nanos)
+        .single()
+        .map(|date| date.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true))
+}
+
+/// Stricter checks for validate_with_options, for values bound for particular readers.
+#[derive(Debug, Clone, Default)]
+pub struct ValidateOptions {
+    /// Reject dates before 1970, in any format. Some LLSD readers
+    /// treat dates as unsigned seconds and cannot read them.
+    pub reject_pre_epoch_dates: bool,
+    /// Notation will be written with raw binary, as with NotationWriteOptions::sized_binary,
+    /// but carried as a character stream, which cannot hold raw bytes. Rejects Binary in notation.
+    pub char_stream_notation: bool,
+}
+
+/// Check that a value can be serialized cleanly in the given format, without producing output.
+/// Catches dates outside years 0000-9999 in the text formats, which use RFC 3339,
+/// characters XML cannot represent, such as NUL, and items too long for binary's 32-bit lengths.
+/// Error describes the first problem found.
+pub fn validate(val: &LLSDValue, format: Format) -> Result<(), Error> {
+    validate_with_options(val, format, &ValidateOptions::default())
+}
+
+/// Check that a value can be serialized cleanly in the given format, with the extra checks in options.
+pub fn validate_with_options(val: &LLSDValue, format: Format, options: &ValidateOptions) -> Result<(), Error> {
+    fn check_text(s: &str, what: &str, format: Format) -> Result<(), Error> {
+        match format {
+            Format::Xml => match s.chars().find(|ch| !xml::is_xml_char(*ch)) {
+                Some(ch) => Err(anyhow!("{} contains U+{:04X}, which XML cannot represent", what, ch as u32)),
+                None => Ok(()),
+            },
+            Format::Binary => check_len(s.len(), what),
+            Format::Notation => Ok(()),
+        }
+    }
+    fn check_len(len: usize, what: &str) -> Result<(), Error> {
+        if len > u32::MAX as usize {
+            Err(anyhow!("{} has length {}, too long for binary LLSD", what, len))
+        } else {
+            Ok(())
+        }
+    }
+    let mut stack: Vec<&LLSDValue> = vec![val];
+    while let Some(val) = stack.pop() {
+        match val {
+            LLSDValue::String(v) => check_text(v, "String", format)?,
+            LLSDValue::URI(v) => check_text(v, "URI", format)?,
+            LLSDValue::Binary(v) if format == Format::Binary => check_len(v.len(), "Binary")?,
+            LLSDValue::Binary(_) if format == Format::Notation && options.char_stream_notation => {
+                return Err(anyhow!("Binary cannot be written raw to character-stream notation"));
+            }
+            LLSDValue::Date(v) if options.reject_pre_epoch_dates && *v < 0.0 => {
+                return Err(anyhow!("Date {} is before 1970", v));
+            }
+            LLSDValue::Date(v) if format != Format::Binary && !(*v >= RFC3339_MIN_SECS as f64 && *v < (RFC3339_MAX_SECS + 1) as f64) => {
+                return Err(anyhow!("Date {} is outside the years RFC 3339 can express", v));
+            }
+            LLSDValue::Map(v) => {
+                if format == Format::Binary {
+                    check_len(v.len(), "Map")?;
+                }
+                for (key, value) in v {
+                    check_text(key, "Map key", format)?;
+                    stack.push(value);
+                }
+            }
+            LLSDValue::Array(v) => {
+                if format == Format::Binary {
+                    check_len(v.len(), "Array")?;
+                }
+                stack.extend(v.iter());
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Write an LLSDValue to a file, in the given format, with header.
+pub fn write_file(path: impl AsRef<Path>, val: &LLSDValue, format: Format) -> Result<(), Error> {
+    let mut writer = BufWriter::new(std::fs::File::create(path)?);
+    to_writer_with_format(&mut writer, val, format)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Serialize in all three formats and return the smallest, with its format.
+/// Binary usually wins, but not always for tiny values.
+/// On a tie, binary is preferred, then notation. XML is not indented.
+pub fn to_most_compact(val: &LLSDValue) -> Result<(Format, Vec<u8>), Error> {
+    let candidates = [
+        (Format::Binary, binary::to_bytes(val)?),
+        (Format::Notation, notation::to_string(val)?.into_bytes()),
+        (Format::Xml, xml::to_string(val, false)?.into_bytes()),
+    ];
+    Ok(candidates
+        .into_iter()
+        .min_by_key(|(_, bytes)| bytes.len())
+        .expect("to_most_compact: no candidates"))
+}
+
+/// Serialize only what changed from old to new, for bandwidth-efficient updates.
+/// The delta is a map. "set" maps paths to new values, and "remove" is an array of removed paths.
+/// Read it back with de::delta_from_bytes and apply it to old with LLSDValue::apply_patch.
+pub fn to_delta(old: &LLSDValue, new: &LLSDValue, format: Format) -> Result<Vec<u8>, Error> {
+    let mut set = LLSDMap::new();
+    let mut remove = Vec::new();
+    for op in old.diff(new) {
+        match op {
+            PatchOp::Set { path, value } => {
+                let _ = set.insert(path, value);
+            }
+            PatchOp::Remove { path } => remove.push(LLSDValue::String(path)),
+        }
+    }
+    let delta = LLSDValue::new_map()
+        .with_key("set", LLSDValue::Map(set))
+        .with_key("remove", LLSDValue::Array(remove));
+    let mut out = Vec::new();
+    to_writer_with_format(&mut out, &delta, format)?;
+    Ok(out)
+}
+
+/// Write an LLSDValue in the given format, with header.
+/// XML is indented.
+pub fn to_writer_with_format<W: Write>(writer: &mut W, val: &LLSDValue, format: Format) -> Result<(), Error> {
+    match format {
+        Format::Xml => xml::to_writer(writer, val, true)?,
+        Format::Binary => binary::to_writer(writer, val)?,
+        Format::Notation => notation::to_writer(writer, val)?,
+    }
+    Ok(())
+}
+
+#[test]
+#[allow(clippy::approx_constant)] // 3.14159 is just a test value
+fn realformattest1() {
+    assert_eq!(RealFormat::Shortest.format(3.14159), "3.14159");
+    assert_eq!(RealFormat::Fixed(2).format(3.14159), "3.14");
+    assert_eq!(RealFormat::Scientific.format(3.14159), "3.14159e0");
+    assert_eq!(RealFormat::Scientific.format(1500.0), "1.5e3");
+}
+
+#[test]
+fn validatetest1() {
+    //  Before year 0 is also before 1970, and RFC 3339 can't express it.
+    let val = LLSDValue::Array(vec![LLSDValue::Integer(1), LLSDValue::Date((RFC3339_MIN_SECS - 1) as f64)]);
+    assert!(validate(&val, Format::Notation).is_err());
+    assert!(validate(&val, Format::Xml).is_err());
+    assert!(validate(&val, Format::Binary).is_ok()); // binary dates are just numbers
+    //  Dates before 1970 are fine by default. ValidateOptions can reject them.
+    let val = LLSDValue::Date(-1.0);
+    assert!(validate(&val, Format::Notation).is_ok());
+    assert!(validate(&LLSDValue::Date(RFC3339_MIN_SECS as f64), Format::Notation).is_ok());
+    assert!(validate(&LLSDValue::Date((RFC3339_MAX_SECS + 1) as f64), Format::Xml).is_err());
+    //  Notation writes binary as base64 by default, so binary is fine there.
+    let val = LLSDValue::Binary(vec![0, 0xff, b'"']);
+    assert!(validate(&val, Format::Notation).is_ok());
+    //  NUL in a string or key is fine except in XML.
+    let val = LLSDValue::Map([("k\0".to_string(), LLSDValue::String("ok".to_string()))].into_iter().collect());
+    assert!(validate(&val, Format::Xml).is_err());
+    assert!(validate(&val, Format::Notation).is_ok());
+    assert!(validate(&val, Format::Binary).is_ok());
+    assert!(validate(&LLSDValue::example(), Format::Xml).is_ok());
+}
+
+#[test]
+fn validatetest2() {
+    //  Stricter checks: pre-epoch dates, and raw binary in character-stream notation.
+    let options = ValidateOptions { reject_pre_epoch_dates: true, char_stream_notation: true };
+    let val = LLSDValue::Array(vec![LLSDValue::Integer(1), LLSDValue::Date(-1.0)]);
+    let err = validate_with_options(&val, Format::Notation, &options).unwrap_err();
+    assert!(err.to_string().contains("before 1970"), "{}", err);
+    assert!(validate_with_options(&val, Format::Binary, &options).is_err());
+    assert!(validate_with_options(&LLSDValue::Date(0.0), Format::Notation, &options).is_ok());
+    let val = LLSDValue::Map([("data".to_string(), LLSDValue::Binary(vec![0, 0xff, b'"']))].into_iter().collect());
+    let err = validate_with_options(&val, Format::Notation, &options).unwrap_err();
+    assert!(err.to_string().contains("character-stream"), "{}", err);
+    //  Binary is fine in the other formats.
+    assert!(validate_with_options(&val, Format::Xml, &options).is_ok());
+    assert!(validate_with_options(&val, Format::Binary, &options).is_ok());
+    //  The default checks still apply.
+    let val = LLSDValue::String("k\0".to_string());
+    assert!(validate_with_options(&val, Format::Xml, &options).is_err());
+}
+
+#[test]
+fn formatdatetest1() {
+    assert_eq!(format_rfc3339_date(1138804193.0).unwrap(), "2006-02-01T14:29:53Z");
+    assert_eq!(format_rfc3339_date(1138804193.25).unwrap(), "2006-02-01T14:29:53.250Z");
+    assert_eq!(format_rfc3339_date(1138804193.123).unwrap(), "2006-02-01T14:29:53.123Z");
+    assert_eq!(format_rfc3339_date(-0.5).unwrap(), "1969-12-31T23:59:59.500Z");
+    assert!(format_rfc3339_date(f64::NAN).is_none());
+    assert!(format_rfc3339_date(1.0e20).is_none());
+}
+
+#[test]
+fn mostcompacttest1() {
+    //  Numbers are compact in binary.
+    let val = LLSDValue::Array((0..100).map(|n| LLSDValue::Real(n as f64 / 7.0)).collect());
+    let (format, bytes) = to_most_compact(&val).unwrap();
+    assert_eq!(format, Format::Binary);
+    assert_eq!(bytes, binary::to_bytes(&val).unwrap());
+    assert_eq!(crate::auto_from_bytes(&bytes).unwrap(), val);
+    //  The notation header is shorter than the binary one.
+    let val = LLSDValue::Integer(5);
+    let (format, bytes) = to_most_compact(&val).unwrap();
+    assert_eq!(format, Format::Notation);
+    assert_eq!(crate::auto_from_bytes(&bytes).unwrap(), val);
+}
+
+#[test]
+fn deltatest1() {
+    let old = crate::notation_from_str(
+        "{'local_id':i8893800,'sides':[i0],'gone':'x','override':{'roughness':r0.2,'metal':r1,'old':!},'same':{'a':i1}}",
+    )
+    .unwrap();
+    let new = crate::notation_from_str(
+        "{'local_id':i8893800,'sides':[i0,i1],'override':{'roughness':r0.5,'metal':r1,'new':'tex/a~b'},'same':{'a':i1},'added':i7}",
+    )
+    .unwrap();
+    let ops = old.diff(&new);
+    assert_eq!(ops.len(), 6); // sides, gone, roughness, old, new, added
+    for format in [Format::Xml, Format::Binary, Format::Notation] {
+        let delta = to_delta(&old, &new, format).unwrap();
+        let mut patched = old.clone();
+        patched.apply_patch(&crate::de::delta_from_bytes(&delta).unwrap()).unwrap();
+        assert_eq!(patched, new);
+    }
+    //  Nothing changed, nothing sent.
+    let empty = crate::de::delta_from_bytes(&to_delta(&old, &old, Format::Binary).unwrap()).unwrap();
+    assert!(empty.is_empty());
+    //  Different types at the top replace the whole value.
+    let mut patched = LLSDValue::Integer(1);
+    patched.apply_patch(&patched.diff(&new)).unwrap();
+    assert_eq!(patched, new);
+}
+
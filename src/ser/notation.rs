@@ -12,9 +12,10 @@
 //  License: LGPL.
 //
 use crate::LLSDValue;
-use anyhow::Error;
-use chrono::{TimeZone};
+use crate::ser::RealFormat;
+use anyhow::{anyhow, Error};
 use base64::Engine;
+use std::io::Write;
 //
 //  Constants
 //
@@ -23,120 +24,161 @@ pub const LLSDNOTATIONPREFIX: &str = "<? llsd/notation ?>\n";
 /// Sentinel, must match exactly.
 pub const LLSDNOTATIONSENTINEL: &str = LLSDNOTATIONPREFIX; 
 
-/// Outputs an LLSDValue as a string of bytes, in LLSD "notation" format.
+/// Options for notation output.
+#[derive(Debug, Clone, Default)]
+pub struct NotationWriteOptions {
+    /// Formatting for reals. Default is shortest round-trip form.
+    pub real_format: RealFormat,
+    /// Leave out the "<? llsd/notation ?>" header,
+    /// for notation embedded in something else.
+    pub omit_header: bool,
+    /// Write Binary as raw bytes, b(len)"...", as used for script uploads,
+    /// instead of b64"...". Output is then a byte stream, not UTF-8,
+    /// so use to_bytes_with_options or to_writer_with_options.
+    pub sized_binary: bool,
+    /// Write strings as raw bytes, s(len)"...", instead of quoted and escaped.
+    pub sized_strings: bool,
+}
+
+/// Outputs an LLSDValue as a string, in LLSD "notation" format.
 pub fn to_string(val: &LLSDValue) -> Result<String, Error> {
-    let mut writer = String::new();
-    writer.push_str(LLSDNOTATIONPREFIX); // prefix
-    generate_value(&mut writer, val)?;
+    to_string_with_options(val, &NotationWriteOptions::default())
+}
+
+/// Outputs an LLSDValue as a string, in LLSD "notation" format, with options.
+pub fn to_string_with_options(val: &LLSDValue, options: &NotationWriteOptions) -> Result<String, Error> {
+    if options.sized_binary {
+        return Err(anyhow!("Sized binary notation is a byte stream, not a string. Use to_bytes_with_options."));
+    }
+    Ok(String::from_utf8(to_bytes_with_options(val, options)?)?)
+}
+
+/// Outputs an LLSDValue as bytes, in LLSD "notation" format, with options.
+pub fn to_bytes_with_options(val: &LLSDValue, options: &NotationWriteOptions) -> Result<Vec<u8>, Error> {
+    let mut writer: Vec<u8> = Vec::new();
+    write_document(&mut writer, val, options)?; // no flush needed for a Vec
     Ok(writer)
 }
 
-//  There could be a corresponding function to generate LLSD notation as bytes,
-//  but that creates transparency problems best avoided.
+/// Outputs an LLSDValue as bytes, in the byte stream form of LLSD "notation" format.
+/// Binary is written as raw bytes, b(len)"...", as in script uploads.
+/// Parse this with de::notation::from_bytes.
+pub fn to_bytes_sized(val: &LLSDValue) -> Result<Vec<u8>, Error> {
+    to_bytes_with_options(val, &NotationWriteOptions { sized_binary: true, ..Default::default() })
+}
+
+/// Outputs an LLSDValue as a string, in LLSD "notation" format, with no header.
+/// For embedding inside another document.
+pub fn to_string_headerless(val: &LLSDValue) -> Result<String, Error> {
+    to_string_with_options(val, &NotationWriteOptions { omit_header: true, ..Default::default() })
+}
 
-/*
-/// Outputs an LLSD value to an output stream
+/// Outputs an LLSD value to an output stream, in LLSD "notation" format.
+/// Nothing is buffered, so large values can be streamed to a file or socket.
 pub fn to_writer<W: Write>(writer: &mut W, val: &LLSDValue) -> Result<(), Error> {
-    writer.write_all(LLSDNOTATIONPREFIX)?; // prefix
-    generate_value(writer, val)?;
+    to_writer_with_options(writer, val, &NotationWriteOptions::default())
+}
+
+/// Outputs an LLSD value to an output stream, in LLSD "notation" format, with options.
+pub fn to_writer_with_options<W: Write>(writer: &mut W, val: &LLSDValue, options: &NotationWriteOptions) -> Result<(), Error> {
+    write_document(writer, val, options)?;
     writer.flush()?;
     Ok(())
 }
-*/
+
+/// Write the header, unless omitted, and the value, without flushing.
+fn write_document<W: Write>(writer: &mut W, val: &LLSDValue, options: &NotationWriteOptions) -> Result<(), Error> {
+    if !options.omit_header {
+        writer.write_all(LLSDNOTATIONPREFIX.as_bytes())?; // prefix
+    }
+    generate_value(writer, val, options)
+}
+
 /// Generate one <TYPE> VALUE </TYPE> output. VALUE is recursive.
-fn generate_value(writer: &mut String, val: &LLSDValue) -> Result<(), Error> {
+fn generate_value<W: Write>(writer: &mut W, val: &LLSDValue, options: &NotationWriteOptions) -> Result<(), Error> {
     //  Emit notation form for all possible types.
     match val {
-        LLSDValue::Undefined => writer.push('!'),
-        LLSDValue::Boolean(v) => writer.push(if *v { 'T' } else { 'F' }),
-        LLSDValue::String(v) => {
-            writer.push('"');
-            writer.push_str(&escape_quotes(v));
-            writer.push('"');
+        LLSDValue::Undefined => writer.write_all(b"!")?,
+        LLSDValue::Boolean(v) => writer.write_all(if *v { b"T" } else { b"F" })?,
+        LLSDValue::String(v) if options.sized_strings => {
+            write!(writer, "s({})\"", v.len())?;
+            writer.write_all(v.as_bytes())?;
+            writer.write_all(b"\"")?;
         }
-        LLSDValue::URI(v) => {
-            writer.push('l');
-            writer.push('"');
-            writer.push_str(&escape_url(v));
-            writer.push('"');
-        }
-        LLSDValue::Integer(v) => {
-            writer.push('i');
-            writer.push_str(&format!("{}",v));
+        LLSDValue::String(v) => {
+            //  Use whichever quote needs no escaping, preferring double.
+            let delim = if v.contains('"') && !v.contains('\'') { '\'' } else { '"' };
+            write!(writer, "{}{}{}", delim, escape_quotes(v, delim), delim)?;
         }
+        LLSDValue::URI(v) => write!(writer, "l\"{}\"", escape_url(v))?,
+        LLSDValue::Integer(v) => write!(writer, "i{}", v)?,
         LLSDValue::Real(v) => {
-            writer.push('r');
-            writer.push_str(&format!("{}",v));
-        }
-        LLSDValue::UUID(v) => {
-            writer.push('u');
-            writer.push_str(&v.to_string());
-        }
-        LLSDValue::Binary(v) => {
-            writer.push('b');
-            writer.push('6');
-            writer.push('4');
-            writer.push('"');
-            writer.push_str(&base64::engine::general_purpose::STANDARD.encode(v));
-            writer.push('"');
+            //  SL spellings for non-finite reals, which the parser reads back.
+            if v.is_nan() {
+                writer.write_all(b"rnan")?
+            } else if v.is_infinite() {
+                writer.write_all(if *v > 0.0 { b"rinf" } else { b"r-inf" })?
+            } else {
+                write!(writer, "r{}", options.real_format.format(*v))?
+            }
         }
-        LLSDValue::Date(v) => {
-            writer.push('d');
-            writer.push_str(&chrono::Utc
-                .timestamp_opt(*v, 0)
-                .earliest()
-                .unwrap() // may panic for times prior to January 1, 1970.
-                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+        LLSDValue::UUID(v) => write!(writer, "u{}", v)?,
+        LLSDValue::Binary(v) if options.sized_binary => {
+            write!(writer, "b({})\"", v.len())?;
+            writer.write_all(v)?;
+            writer.write_all(b"\"")?;
         }
+        LLSDValue::Binary(v) => write!(writer, "b64\"{}\"", base64::engine::general_purpose::STANDARD.encode(v))?,
+        LLSDValue::Date(v) => write!(
+            writer,
+            "d\"{}\"",
+            crate::ser::format_rfc3339_date(*v)
+                .ok_or_else(|| anyhow!("Date {} is too far from 1970 to write as a date", v))?
+        )?,
 
         //  Map is {  key : value, key : value ... }
         LLSDValue::Map(v) => {
             //  Curly bracketed list
-            writer.push('{');
+            writer.write_all(b"{")?;
             //  Output key/value pairs
             let mut first: bool = true;
             for (key, value) in v {
                 if !first {
-                    writer.push(',');
-                    writer.push('\n');
+                    writer.write_all(b",\n")?;
                 }
                 first = false;
-                writer.push('\'');
-                writer.push_str(key);
-                writer.push('\'');
-                writer.push(':');
-                generate_value(writer, value)?;
+                write!(writer, "'{}':", escape_quotes(key, '\''))?; // keys use the same escapes as values
+                generate_value(writer, value, options)?;
             }
-            writer.push('}');
+            writer.write_all(b"}")?;
         }
         //  Array is [ child, child ... ]
         LLSDValue::Array(v) => {
             //  Square bracketed list
-            writer.push('[');
+            writer.write_all(b"[")?;
             //  Output array entries
             let mut first: bool = true;
             for value in v {
                 if !first {
-                    writer.push(',');
-                    writer.push('\n');
+                    writer.write_all(b",\n")?;
                 }
                 first = false;
-                generate_value(writer, value)?;           
+                generate_value(writer, value, options)?;
             }
-            writer.push(']');
+            writer.write_all(b"]")?;
         }
     };
     Ok(())
 }
 
-/// Escape double quote as \", and of course \ as \\.
-fn escape_quotes(s: &str) -> String {
+/// Escape the quote character in use, and of course \ as \\.
+fn escape_quotes(s: &str, delim: char) -> String {
     let mut writer = String::new();
     for ch in s.chars() {
-        match ch {
-            '"' | '\\' => { writer.push('\\'); writer.push(ch) }
-            _ => writer.push(ch)
+        if ch == delim || ch == '\\' {
+            writer.push('\\');
         }
+        writer.push(ch)
     }     
     writer
 }
@@ -225,6 +267,13 @@ fn notationgentest1() {
         */
     }
     trytestcase(TESTXML1);
+    //  Every variant.
+    {
+        let example = LLSDValue::example();
+        let generated = to_string(&example).unwrap();
+        assert_eq!(crate::de::notation::from_str(&generated[LLSDNOTATIONPREFIX.len()..]).unwrap(), example);
+        assert_eq!(crate::de::notation::from_bytes(&generated.as_bytes()[LLSDNOTATIONPREFIX.len()..]).unwrap(), example);
+    }
     //  Test NAN case
     {
         let parsed1 =  crate::de::xml::from_str(TESTXMLNAN).unwrap();
@@ -234,3 +283,178 @@ fn notationgentest1() {
         println!("Generated Notation format:\n{}", generated);
     }
 }
+
+#[test]
+fn notationgenquotetest1() {
+    //  Double quotes inside, so single-quoted with no escapes.
+    let val = LLSDValue::String("say \"hi\"".to_string());
+    let s = to_string(&val).unwrap();
+    assert_eq!(s, format!("{}'say \"hi\"'", LLSDNOTATIONPREFIX));
+    assert_eq!(crate::de::notation::from_str(&s[LLSDNOTATIONPREFIX.len()..]).unwrap(), val);
+    //  Both kinds of quote, so double-quoted and escaped.
+    let val = LLSDValue::String("it's \"hi\" \\".to_string());
+    let s = to_string(&val).unwrap();
+    assert_eq!(s, format!("{}\"it's \\\"hi\\\" \\\\\"", LLSDNOTATIONPREFIX));
+    assert_eq!(crate::de::notation::from_str(&s[LLSDNOTATIONPREFIX.len()..]).unwrap(), val);
+    //  Plain strings stay double-quoted.
+    assert_eq!(to_string(&LLSDValue::String("abc".to_string())).unwrap(), format!("{}\"abc\"", LLSDNOTATIONPREFIX));
+}
+
+#[test]
+fn notationgendatetest1() {
+    //  Dates are quoted, so they parse back.
+    let val = LLSDValue::Array(vec![LLSDValue::Date(1138804193.0), LLSDValue::Integer(1)]);
+    let s = to_string(&val).unwrap();
+    assert_eq!(s, format!("{}[d\"2006-02-01T14:29:53Z\",\ni1]", LLSDNOTATIONPREFIX));
+    assert_eq!(crate::de::notation::from_str(&s[LLSDNOTATIONPREFIX.len()..]).unwrap(), val);
+}
+
+#[test]
+fn notationgenkeyescapetest1() {
+    //  Keys with quotes and backslashes must be escaped like string values.
+    let val = LLSDValue::Map(
+        ["it's", "say \"hi\"", "back\\slash", "plain"]
+            .iter()
+            .map(|k| (k.to_string(), LLSDValue::String(k.to_string())))
+            .collect(),
+    );
+    let s = to_string(&val).unwrap();
+    assert!(s.contains("'it\\'s':"));
+    assert_eq!(crate::de::notation::from_str(&s[LLSDNOTATIONPREFIX.len()..]).unwrap(), val);
+    assert_eq!(crate::de::notation::from_bytes(&s.as_bytes()[LLSDNOTATIONPREFIX.len()..]).unwrap(), val);
+}
+
+#[test]
+#[allow(clippy::approx_constant)] // 3.14159 is just a test value
+fn notationgenrealformattest1() {
+    let val = LLSDValue::Real(3.14159);
+    let options = NotationWriteOptions { real_format: RealFormat::Fixed(2), ..Default::default() };
+    assert_eq!(to_string_with_options(&val, &options).unwrap(), format!("{}r3.14", LLSDNOTATIONPREFIX));
+    let options = NotationWriteOptions { real_format: RealFormat::Scientific, ..Default::default() };
+    let s = to_string_with_options(&val, &options).unwrap();
+    assert_eq!(s, format!("{}r3.14159e0", LLSDNOTATIONPREFIX));
+    assert_eq!(to_string(&val).unwrap(), format!("{}r3.14159", LLSDNOTATIONPREFIX));
+}
+
+#[test]
+fn notationgenuriquotetest1() {
+    //  URIs are always double quoted, and URL encoding takes care of both quote characters.
+    for uri in ["http://example.com/it's", "http://example.com/say \"hi\"", "http://example.com/'\"\\%+&"] {
+        let val = LLSDValue::URI(uri.to_string());
+        let s = to_string(&val).unwrap();
+        let body = &s[LLSDNOTATIONPREFIX.len()..];
+        assert!(body.starts_with("l\"") && body.ends_with('"'));
+        assert!(!body[2..body.len() - 1].contains(['"', '\'', '\\']));
+        assert_eq!(crate::de::notation::from_str(&s).unwrap(), val);
+        assert_eq!(crate::de::notation::from_bytes(s.as_bytes()).unwrap(), val);
+    }
+    //  Single-quoted URIs from other producers parse the same way.
+    assert_eq!(
+        crate::de::notation::from_str("l'http://example.com/it%27s'").unwrap(),
+        LLSDValue::URI("http://example.com/it's".to_string())
+    );
+}
+
+#[test]
+fn notationgenwritertest1() {
+    //  Streamed output must match the string form exactly.
+    let val = LLSDValue::example();
+    let mut w: Vec<u8> = Vec::new();
+    to_writer(&mut w, &val).unwrap();
+    assert_eq!(w, to_string(&val).unwrap().into_bytes());
+    assert_eq!(crate::de::notation::from_bytes(&w).unwrap(), val);
+}
+
+#[test]
+#[cfg(feature = "ordered-map")]
+fn notationgenorderedmaptest1() {
+    //  With ordered maps, keys come out sorted, whatever the insertion order.
+    let val = LLSDValue::Map(crate::LLSDMap::new())
+        .with_key("zeta", LLSDValue::Integer(1))
+        .with_key("alpha", LLSDValue::Integer(2))
+        .with_key("mid", LLSDValue::Integer(3));
+    let s = to_string(&val).unwrap();
+    assert_eq!(s, format!("{}{{'alpha':i2,\n'mid':i3,\n'zeta':i1}}", LLSDNOTATIONPREFIX));
+    let xml = crate::ser::xml::to_string(&val, false).unwrap();
+    let (a, m, z) = (xml.find("alpha").unwrap(), xml.find("mid").unwrap(), xml.find("zeta").unwrap());
+    assert!(a < m && m < z);
+}
+
+#[test]
+fn notationgennonfinitetest1() {
+    let val = LLSDValue::Array(vec![
+        LLSDValue::Real(f64::INFINITY),
+        LLSDValue::Real(f64::NEG_INFINITY),
+        LLSDValue::Real(f64::NAN),
+        LLSDValue::Real(1.5),
+    ]);
+    let s = to_string(&val).unwrap();
+    assert_eq!(&s[LLSDNOTATIONPREFIX.len()..], "[rinf,\nr-inf,\nrnan,\nr1.5]");
+    for parsed in [crate::de::notation::from_str(&s).unwrap(), crate::de::notation::from_bytes(s.as_bytes()).unwrap()] {
+        let items = parsed.as_array().unwrap();
+        assert_eq!(items[0], LLSDValue::Real(f64::INFINITY));
+        assert_eq!(items[1], LLSDValue::Real(f64::NEG_INFINITY));
+        assert!(items[2].as_real().unwrap().is_nan());
+        assert_eq!(items[3], LLSDValue::Real(1.5));
+    }
+}
+
+#[test]
+fn notationgenheaderlesstest1() {
+    let val = LLSDValue::example();
+    let s = to_string_headerless(&val).unwrap();
+    assert!(!s.contains("llsd/notation"));
+    assert_eq!(crate::de::notation::from_str(&s).unwrap(), val);
+    assert_eq!(to_string(&val).unwrap().len(), s.len() + LLSDNOTATIONPREFIX.len());
+    //  Embedded as a string inside another notation document.
+    let outer = LLSDValue::Map(crate::LLSDMap::new()).with_key("inner", LLSDValue::String(s.clone()));
+    let parsed = crate::de::notation::from_str(&to_string(&outer).unwrap()).unwrap();
+    let inner = parsed.get("inner").and_then(|v| v.as_string()).unwrap();
+    assert_eq!(crate::de::notation::from_str(inner).unwrap(), val);
+    //  Writer form, with options.
+    let mut out = Vec::new();
+    to_writer_with_options(&mut out, &val, &NotationWriteOptions { omit_header: true, ..Default::default() }).unwrap();
+    assert_eq!(out, s.as_bytes());
+}
+
+#[test]
+fn notationgenolddatetest1() {
+    //  Dates before 1970 are negative, and are written normally.
+    for (secs, text) in [(-1.0, "1969-12-31T23:59:59Z"), (-62135596800.0, "0001-01-01T00:00:00Z"), (-100000.0, "1969-12-30T20:13:20Z")] {
+        let val = LLSDValue::Date(secs);
+        let s = to_string(&val).unwrap();
+        assert_eq!(&s[LLSDNOTATIONPREFIX.len()..], format!("d\"{}\"", text));
+        assert_eq!(crate::de::notation::from_str(&s).unwrap(), val);
+    }
+    //  Dates chrono cannot represent are errors, not panics.
+    assert!(to_string(&LLSDValue::Date(f64::NAN)).is_err());
+    assert!(to_string(&LLSDValue::Date(1.0e20)).is_err());
+    assert!(crate::ser::xml::to_string(&LLSDValue::Date(f64::NAN), false).is_err());
+}
+
+#[test]
+fn notationgensizedtest1() {
+    //  The script upload example from the LL wiki, as in de::notation's notationparse3.
+    const SCRIPT: &str = "default\n{\n    state_entry()\n    {\n        llSay(0, \"Hello, Avatar!\");\n    }\n\n    touch_start(integer total_number)\n    {\n        llSay(0, \"Touched.\");\n    }\n}";
+    let val = LLSDValue::Array(vec![
+        LLSDValue::String("0123456789".to_string()),
+        LLSDValue::String("Where's the beef?".to_string()),
+        LLSDValue::Binary(SCRIPT.as_bytes().to_vec()),
+        LLSDValue::Binary(vec![0, 0xff, b'"', b'\\']), // not UTF-8, and quote characters
+    ]);
+    let b = to_bytes_sized(&val).unwrap();
+    let expected_script = format!("b(158)\"{}\"", SCRIPT);
+    assert!(b.windows(expected_script.len()).any(|w| w == expected_script.as_bytes()));
+    let expected_raw = b"b(4)\"\x00\xff\"\\\"";
+    assert!(b.windows(expected_raw.len()).any(|w| w == expected_raw));
+    assert!(std::str::from_utf8(&b).is_err()); // byte stream, not UTF-8
+    assert_eq!(crate::de::notation::from_bytes(&b).unwrap(), val);
+    //  Sized strings too.
+    let options = NotationWriteOptions { sized_binary: true, sized_strings: true, omit_header: true, ..Default::default() };
+    let b = to_bytes_with_options(&val, &options).unwrap();
+    assert!(b.starts_with(b"[s(10)\"0123456789\",\ns(17)\"Where's the beef?\""));
+    assert_eq!(crate::de::notation::from_bytes(&b).unwrap(), val);
+    //  Not available as a string.
+    assert!(to_string_with_options(&val, &options).is_err());
+}
+
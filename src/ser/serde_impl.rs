@@ -0,0 +1,367 @@
+//! # ser/serde_impl -- serialize Rust data to an LLSDValue tree, using Serde.
+//!
+//!  Library for serializing and de-serializing data in
+//!  Linden Lab Structured Data format.
+//!
+//!  Format documentation is at http://wiki.secondlife.com/wiki/LLSD
+//!
+//!  Any type implementing Serialize becomes an LLSDValue, which
+//!  can then be written in any of the three formats.
+//!
+//!  Structs and maps become Map, sequences and tuples become Array,
+//!  integers become Integer if they fit in an i32, floats become Real,
+//!  None and () become Undefined. Serde sends Vec<u8> as a sequence,
+//!  so it becomes an Array of Integer, not Binary. To get Binary, mark the field
+//!  `#[serde(with = "serde_bytes")]`, or use any type whose Serialize
+//!  calls serialize_bytes.
+//!  Enum variants are externally tagged: a unit variant is its name as a String,
+//!  others are a Map with one entry, the variant name.
+//
+//  Animats
+//  October, 2026.
+//  License: LGPL.
+//
+use crate::{LLSDMap, LLSDValue};
+use serde::ser::{self, Serialize};
+use std::fmt;
+
+/// Convert anything serializable into an LLSDValue tree.
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<LLSDValue, anyhow::Error> {
+    Ok(value.serialize(ValueSerializer)?)
+}
+
+/// Serde serialization error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+/// Integer that must fit in an LLSD Integer, which is an i32.
+fn integer<T: TryInto<i32> + fmt::Display + Copy>(v: T, typename: &str) -> Result<LLSDValue, Error> {
+    match v.try_into() {
+        Ok(v) => Ok(LLSDValue::Integer(v)),
+        Err(_) => Err(Error(format!(
+            "{} value {} does not fit in an LLSD Integer, which is an i32",
+            typename, v
+        ))),
+    }
+}
+
+/// Serializer whose output is an LLSDValue.
+struct ValueSerializer;
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = LLSDValue;
+    type Error = Error;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = VariantSerializer<SeqSerializer>;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = VariantSerializer<MapSerializer>;
+
+    fn serialize_bool(self, v: bool) -> Result<LLSDValue, Error> {
+        Ok(LLSDValue::Boolean(v))
+    }
+    fn serialize_i8(self, v: i8) -> Result<LLSDValue, Error> {
+        Ok(LLSDValue::Integer(v.into()))
+    }
+    fn serialize_i16(self, v: i16) -> Result<LLSDValue, Error> {
+        Ok(LLSDValue::Integer(v.into()))
+    }
+    fn serialize_i32(self, v: i32) -> Result<LLSDValue, Error> {
+        Ok(LLSDValue::Integer(v))
+    }
+    fn serialize_i64(self, v: i64) -> Result<LLSDValue, Error> {
+        integer(v, "i64")
+    }
+    fn serialize_i128(self, v: i128) -> Result<LLSDValue, Error> {
+        integer(v, "i128")
+    }
+    fn serialize_u8(self, v: u8) -> Result<LLSDValue, Error> {
+        Ok(LLSDValue::Integer(v.into()))
+    }
+    fn serialize_u16(self, v: u16) -> Result<LLSDValue, Error> {
+        Ok(LLSDValue::Integer(v.into()))
+    }
+    fn serialize_u32(self, v: u32) -> Result<LLSDValue, Error> {
+        integer(v, "u32")
+    }
+    fn serialize_u64(self, v: u64) -> Result<LLSDValue, Error> {
+        integer(v, "u64")
+    }
+    fn serialize_u128(self, v: u128) -> Result<LLSDValue, Error> {
+        integer(v, "u128")
+    }
+    fn serialize_f32(self, v: f32) -> Result<LLSDValue, Error> {
+        Ok(LLSDValue::Real(v.into()))
+    }
+    fn serialize_f64(self, v: f64) -> Result<LLSDValue, Error> {
+        Ok(LLSDValue::Real(v))
+    }
+    fn serialize_char(self, v: char) -> Result<LLSDValue, Error> {
+        Ok(LLSDValue::String(v.to_string()))
+    }
+    fn serialize_str(self, v: &str) -> Result<LLSDValue, Error> {
+        Ok(LLSDValue::String(v.to_string()))
+    }
+    fn serialize_bytes(self, v: &[u8]) -> Result<LLSDValue, Error> {
+        Ok(LLSDValue::Binary(v.to_vec()))
+    }
+    fn serialize_none(self) -> Result<LLSDValue, Error> {
+        Ok(LLSDValue::Undefined)
+    }
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<LLSDValue, Error> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<LLSDValue, Error> {
+        Ok(LLSDValue::Undefined)
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<LLSDValue, Error> {
+        Ok(LLSDValue::Undefined)
+    }
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<LLSDValue, Error> {
+        Ok(LLSDValue::String(variant.to_string()))
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<LLSDValue, Error> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<LLSDValue, Error> {
+        Ok(LLSDValue::Map([(variant.to_string(), value.serialize(self)?)].into_iter().collect()))
+    }
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer, Error> {
+        Ok(SeqSerializer(Vec::with_capacity(len.unwrap_or(0))))
+    }
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer, Error> {
+        self.serialize_seq(Some(len))
+    }
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SeqSerializer, Error> {
+        self.serialize_seq(Some(len))
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<VariantSerializer<SeqSerializer>, Error> {
+        Ok(VariantSerializer { variant, inner: self.serialize_seq(Some(len))? })
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<MapSerializer, Error> {
+        Ok(MapSerializer { map: LLSDMap::new(), key: None })
+    }
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<MapSerializer, Error> {
+        self.serialize_map(Some(len))
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<VariantSerializer<MapSerializer>, Error> {
+        Ok(VariantSerializer { variant, inner: self.serialize_map(Some(len))? })
+    }
+}
+
+/// Builds an Array.
+struct SeqSerializer(Vec<LLSDValue>);
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = LLSDValue;
+    type Error = Error;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.0.push(value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+    fn end(self) -> Result<LLSDValue, Error> {
+        Ok(LLSDValue::Array(self.0))
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = LLSDValue;
+    type Error = Error;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+    fn end(self) -> Result<LLSDValue, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = LLSDValue;
+    type Error = Error;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+    fn end(self) -> Result<LLSDValue, Error> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+/// Builds a Map. Keys must come out as strings or integers.
+struct MapSerializer {
+    map: LLSDMap,
+    /// Key waiting for its value
+    key: Option<String>,
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = LLSDValue;
+    type Error = Error;
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.key = Some(match key.serialize(ValueSerializer)? {
+            LLSDValue::String(s) => s,
+            LLSDValue::Integer(v) => v.to_string(),
+            other => return Err(Error(format!("LLSD map keys must be strings, not {}", other.type_name()))),
+        });
+        Ok(())
+    }
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self.key.take().ok_or_else(|| Error("Map value without a key".to_string()))?;
+        let _ = self.map.insert(key, value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+    fn end(self) -> Result<LLSDValue, Error> {
+        Ok(LLSDValue::Map(self.map))
+    }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = LLSDValue;
+    type Error = Error;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        let _ = self.map.insert(key.to_string(), value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+    fn end(self) -> Result<LLSDValue, Error> {
+        Ok(LLSDValue::Map(self.map))
+    }
+}
+
+/// Builds the content of an enum variant, then wraps it in a one-entry map.
+struct VariantSerializer<S> {
+    variant: &'static str,
+    inner: S,
+}
+
+impl<S> VariantSerializer<S> {
+    fn wrap(variant: &str, value: LLSDValue) -> LLSDValue {
+        LLSDValue::Map([(variant.to_string(), value)].into_iter().collect())
+    }
+}
+
+impl ser::SerializeTupleVariant for VariantSerializer<SeqSerializer> {
+    type Ok = LLSDValue;
+    type Error = Error;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        ser::SerializeSeq::serialize_element(&mut self.inner, value)
+    }
+    fn end(self) -> Result<LLSDValue, Error> {
+        Ok(Self::wrap(self.variant, ser::SerializeSeq::end(self.inner)?))
+    }
+}
+
+impl ser::SerializeStructVariant for VariantSerializer<MapSerializer> {
+    type Ok = LLSDValue;
+    type Error = Error;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        ser::SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+    fn end(self) -> Result<LLSDValue, Error> {
+        Ok(Self::wrap(self.variant, ser::SerializeStruct::end(self.inner)?))
+    }
+}
+
+#[test]
+fn serdesertest1() {
+    use serde::Serialize;
+    #[derive(Serialize)]
+    struct NamedPoint {
+        name: String,
+        x: f64,
+        y: f32,
+        id: u32,
+        tags: Vec<String>,
+        note: Option<String>,
+        shape: Shape,
+    }
+    #[derive(Serialize)]
+    enum Shape {
+        Circle(f64),
+        Square,
+    }
+    let pt = NamedPoint {
+        name: "origin".to_string(),
+        x: 1.5,
+        y: -2.0,
+        id: 7,
+        tags: vec!["a".to_string()],
+        note: None,
+        shape: Shape::Circle(2.0),
+    };
+    let val = to_value(&pt).unwrap();
+    let expected = crate::notation_from_str(
+        "{'name':'origin','x':r1.5,'y':r-2,'id':i7,'tags':['a'],'note':!,'shape':{'Circle':r2}}",
+    )
+    .unwrap();
+    assert_eq!(val, expected);
+    assert_eq!(to_value(&Shape::Square).unwrap(), LLSDValue::String("Square".to_string()));
+    //  Straight to XML.
+    let xml = crate::to_string(&val, false).unwrap();
+    assert_eq!(crate::from_str(&xml).unwrap(), expected);
+    //  Integers must fit in an i32.
+    let err = to_value(&5_000_000_000u64).unwrap_err().to_string();
+    assert!(err.contains("u64 value 5000000000"), "{}", err);
+    assert_eq!(to_value(&(1u8, -3i64)).unwrap(), crate::notation_from_str("[i1,i-3]").unwrap());
+    //  Map keys must be strings or integers.
+    let m: std::collections::HashMap<u8, bool> = [(4, true)].into_iter().collect();
+    assert_eq!(to_value(&m).unwrap(), crate::notation_from_str("{'4':T}").unwrap());
+    let m: std::collections::HashMap<(u8, u8), bool> = [((1, 2), true)].into_iter().collect();
+    assert!(to_value(&m).is_err());
+}
+
+#[test]
+fn serdesertest2() {
+    use serde::Serialize;
+    //  Bytes sent with serialize_bytes become Binary. Plain Vec<u8> is a sequence.
+    struct Bytes(Vec<u8>);
+    impl Serialize for Bytes {
+        fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+    #[derive(Serialize)]
+    struct Asset {
+        data: Bytes,
+        raw: Vec<u8>,
+    }
+    let asset = Asset { data: Bytes(vec![0, 1, 0xff]), raw: vec![2, 3] };
+    let val = to_value(&asset).unwrap();
+    assert_eq!(val.get("data"), Some(&LLSDValue::Binary(vec![0, 1, 0xff])));
+    assert_eq!(
+        val.get("raw"),
+        Some(&LLSDValue::Array(vec![LLSDValue::Integer(2), LLSDValue::Integer(3)]))
+    );
+}
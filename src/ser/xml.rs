@@ -15,12 +15,12 @@
 //
 //  Much like Serde-JSON, this will serialize and de-serialize only trees of LLSDValue items.
 
-use crate::LLSDValue;
-use anyhow::Error;
+use crate::{LLSDMap, LLSDValue};
+use crate::ser::RealFormat;
+use anyhow::{anyhow, Error};
 use base64;
 use base64::Engine;
-use chrono;
-use chrono::TimeZone;
+use std::collections::HashSet;
 use std::io::Write;
 //
 //  Constants
@@ -34,15 +34,45 @@ const INDENT: usize = 4; // indent 4 spaces if asked
 // Rust types the serializer is able to produce as output.
 //
 
+/// Options for XML output.
+#[derive(Debug, Clone, Default)]
+pub struct XmlWriteOptions {
+    /// Indent by 4 spaces per level.
+    pub do_indent: bool,
+    /// Emit map keys in this order, for byte-exact output.
+    /// Keys not in the list follow, in sorted order.
+    /// If None, keys come out in map order.
+    pub key_order: Option<Vec<String>>,
+    /// Wrap base64 binary data at this column, as SL does.
+    /// If None, binary data is on one line.
+    pub base64_wrap: Option<usize>,
+    /// Formatting for reals. Default is shortest round-trip form.
+    pub real_format: RealFormat,
+    /// Drop characters that XML cannot represent, such as NUL.
+    /// If false, they are an error.
+    pub strip_invalid_chars: bool,
+    /// Single quotes in the <?xml ...?> declaration, as SL sometimes writes it.
+    pub single_quote_declaration: bool,
+    /// No newline between the <?xml ...?> declaration and <llsd>.
+    pub omit_declaration_newline: bool,
+}
+
 /// LLSDValue to Writer
 pub fn to_writer<W: Write>(
     writer: &mut W,
     value: &LLSDValue,
     do_indent: bool,
 ) -> Result<(), Error> {
-    write!(writer, "{}", LLSDXMLPREFIX)?; // Standard XML prefix
-    generate_value(writer, value, if do_indent { INDENT } else { 0 }, 0);
-    write!(writer, "</llsd>")?;
+    to_writer_with_options(writer, value, &XmlWriteOptions { do_indent, ..Default::default() })
+}
+
+/// LLSDValue to Writer, with options.
+pub fn to_writer_with_options<W: Write>(
+    writer: &mut W,
+    value: &LLSDValue,
+    options: &XmlWriteOptions,
+) -> Result<(), Error> {
+    write_document(writer, value, options)?;
     writer.flush()?;
     Ok(())
 }
@@ -50,37 +80,89 @@ pub fn to_writer<W: Write>(
 /// LLSDValue to String.
 /// Pretty prints out the value as XML. Indents by 4 spaces if requested.
 pub fn to_string(val: &LLSDValue, do_indent: bool) -> Result<String, Error> {
+    to_string_with_options(val, &XmlWriteOptions { do_indent, ..Default::default() })
+}
+
+/// LLSDValue to String, with options.
+pub fn to_string_with_options(val: &LLSDValue, options: &XmlWriteOptions) -> Result<String, Error> {
     let mut s: Vec<u8> = Vec::new();
-    to_writer(&mut s, val, do_indent)?;
-    Ok(std::str::from_utf8(&s)?.to_string())
+    write_document(&mut s, val, options)?; // no flush needed for a Vec
+    Ok(String::from_utf8(s)?)
+}
+
+/// Write the whole XML document, without flushing.
+fn write_document<W: Write>(writer: &mut W, value: &LLSDValue, options: &XmlWriteOptions) -> Result<(), Error> {
+    if options.single_quote_declaration || options.omit_declaration_newline {
+        let decl = if options.single_quote_declaration {
+            "<?xml version='1.0' encoding='UTF-8'?>"
+        } else {
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>"
+        };
+        let newline = if options.omit_declaration_newline { "" } else { "\n" };
+        writeln!(writer, "{}{}<llsd>", decl, newline)?;
+    } else {
+        write!(writer, "{}", LLSDXMLPREFIX)?; // Standard XML prefix
+    }
+    generate_value(writer, value, options, 0)?;
+    write!(writer, "</llsd>")?;
+    Ok(())
+}
+
+/// Map keys in output order.
+fn ordered_keys<'a>(map: &'a LLSDMap, options: &XmlWriteOptions) -> Vec<&'a String> {
+    match &options.key_order {
+        None => map.keys().collect(),
+        Some(order) => {
+            //  Listed keys first, in list order, each once even if listed twice.
+            let mut listed: HashSet<&str> = HashSet::new();
+            let mut keys: Vec<&String> = order
+                .iter()
+                .filter(|k| listed.insert(k.as_str()))
+                .filter_map(|k| map.get_key_value(k).map(|(k, _)| k))
+                .collect();
+            //  Then everything else, sorted.
+            let mut rest: Vec<&String> = map.keys().filter(|k| !listed.contains(k.as_str())).collect();
+            rest.sort();
+            keys.extend(rest);
+            keys
+        }
+    }
 }
 
 /// Generate one <TYPE> VALUE </TYPE> output. VALUE is recursive.
-fn generate_value<W: Write>(writer: &mut W, val: &LLSDValue, spaces: usize, indent: usize) {
+fn generate_value<W: Write>(writer: &mut W, val: &LLSDValue, options: &XmlWriteOptions, indent: usize) -> Result<(), Error> {
+    let spaces = if options.do_indent { INDENT } else { 0 };
     //  Output a single tag
-    fn tag<W: Write>(writer: &mut W, tag: &str, close: bool, indent: usize) {
+    fn tag<W: Write>(writer: &mut W, tag: &str, close: bool, indent: usize) -> Result<(), Error> {
         if indent > 0 {
-            let _ = write!(writer, "{:1$}", " ", indent);
+            write!(writer, "{:1$}", " ", indent)?;
         };
-        let _ = writeln!(writer, "<{}{}>", if close { "/" } else { "" }, tag);
+        writeln!(writer, "<{}{}>", if close { "/" } else { "" }, tag)?;
+        Ok(())
     }
 
-    //  Internal fn - write out one tag with a value.
-    fn tag_value<W: Write>(writer: &mut W, tag: &str, text: &str, indent: usize) {
+    //  Internal fn - write out one tag with a value, already escaped.
+    fn tag_text<W: Write>(writer: &mut W, tag: &str, text: &str, indent: usize) -> Result<(), Error> {
         if indent > 0 {
-            let _ = write!(writer, "{:1$}", " ", indent);
+            write!(writer, "{:1$}", " ", indent)?;
         };
         if text.is_empty() {
             // if empty, write as null tag
-            let _ = writeln!(writer, "<{} />", tag);
+            writeln!(writer, "<{} />", tag)?;
         } else {
-            let _ = writeln!(writer, "<{}>{}</{}>", tag, xml_escape(text), tag);
+            writeln!(writer, "<{}>{}</{}>", tag, text, tag)?;
         }
+        Ok(())
+    }
+
+    //  Internal fn - write out one tag with a value.
+    fn tag_value<W: Write>(writer: &mut W, tag: &str, text: &str, options: &XmlWriteOptions, indent: usize) -> Result<(), Error> {
+        tag_text(writer, tag, &xml_escape(text, options.strip_invalid_chars)?, indent)
     }
 
     //  Use SL "nan", not Rust "NaN"
-    fn f64_to_xml(v: f64) -> String {
-        let ss = v.to_string();
+    fn f64_to_xml(v: f64, real_format: RealFormat) -> String {
+        let ss = real_format.format(v);
         if ss == "NaN" {
             "nan".to_string()
         } else {
@@ -89,51 +171,70 @@ fn generate_value<W: Write>(writer: &mut W, val: &LLSDValue, spaces: usize, inde
     }
     //  Emit XML for all possible types.
     match val {
-        LLSDValue::Undefined => tag_value(writer, "undef", "", indent),
+        LLSDValue::Undefined => tag_text(writer, "undef", "", indent)?,
         LLSDValue::Boolean(v) => {
-            tag_value(writer, "boolean", if *v { "true" } else { "false" }, indent)
+            tag_text(writer, "boolean", if *v { "true" } else { "false" }, indent)?
         }
-        LLSDValue::String(v) => tag_value(writer, "string", v.as_str(), indent),
-        LLSDValue::URI(v) => tag_value(writer, "uri", v.as_str(), indent),
-        LLSDValue::Integer(v) => tag_value(writer, "integer", v.to_string().as_str(), indent),
-        LLSDValue::Real(v) => tag_value(writer, "real", f64_to_xml(*v).as_str(), indent),
-        LLSDValue::UUID(v) => tag_value(writer, "uuid", v.to_string().as_str(), indent),
-        LLSDValue::Binary(v) => tag_value(
+        LLSDValue::String(v) => tag_value(writer, "string", v.as_str(), options, indent)?,
+        LLSDValue::URI(v) => tag_value(writer, "uri", v.as_str(), options, indent)?,
+        LLSDValue::Integer(v) => tag_text(writer, "integer", v.to_string().as_str(), indent)?,
+        LLSDValue::Real(v) => tag_text(writer, "real", f64_to_xml(*v, options.real_format).as_str(), indent)?,
+        LLSDValue::UUID(v) => tag_text(writer, "uuid", v.to_string().as_str(), indent)?,
+        //  Base64 needs no escaping, and line breaks from wrapping stay as they are.
+        LLSDValue::Binary(v) => tag_text(
             writer,
             "binary",
-            base64::engine::general_purpose::STANDARD.encode(v).as_str(),
+            wrap_lines(&base64::engine::general_purpose::STANDARD.encode(v), options.base64_wrap).as_str(),
             indent,
-        ),
-        LLSDValue::Date(v) => tag_value(
+        )?,
+        LLSDValue::Date(v) => tag_text(
             writer,
             "date",
-            &chrono::Utc
-                .timestamp_opt(*v, 0)
-                .earliest()
-                .unwrap() // may panic for times prior to January 1, 1970.
-                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            &crate::ser::format_rfc3339_date(*v)
+                .ok_or_else(|| anyhow!("Date {} is too far from 1970 to write as a date", v))?,
             indent,
-        ),
+        )?,
         LLSDValue::Map(v) => {
-            tag(writer, "map", false, indent);
-            for (key, value) in v {
-                tag_value(writer, "key", key, indent + spaces);
-                generate_value(writer, value, spaces, indent + spaces);
+            tag(writer, "map", false, indent)?;
+            for key in ordered_keys(v, options) {
+                tag_value(writer, "key", key, options, indent + spaces)?;
+                generate_value(writer, &v[key], options, indent + spaces)?;
             }
-            tag(writer, "map", true, indent);
+            tag(writer, "map", true, indent)?;
         }
         LLSDValue::Array(v) => {
-            tag(writer, "array", false, indent);
+            tag(writer, "array", false, indent)?;
             for value in v {
-                generate_value(writer, value, spaces, indent + spaces);
+                generate_value(writer, value, options, indent + spaces)?;
             }
-            tag(writer, "array", true, indent);
+            tag(writer, "array", true, indent)?;
         }
     };
+    Ok(())
+}
+
+/// Break text into lines of at most width chars. Text must be ASCII.
+fn wrap_lines(text: &str, width: Option<usize>) -> String {
+    match width {
+        Some(width) if width > 0 && text.len() > width => text
+            .as_bytes()
+            .chunks(width)
+            .map(|line| std::str::from_utf8(line).expect("wrap_lines: not ASCII"))
+            .collect::<Vec<&str>>()
+            .join("\n"),
+        _ => text.to_string(),
+    }
+}
+
+/// Can this character appear in an XML 1.0 document at all, even escaped?
+pub(crate) fn is_xml_char(ch: char) -> bool {
+    !matches!(ch, '\u{0}'..='\u{8}' | '\u{b}' | '\u{c}' | '\u{e}'..='\u{1f}' | '\u{fffe}' | '\u{ffff}')
 }
 
 /// XML standard character escapes.
-fn xml_escape(unescaped: &str) -> String {
+/// Control characters legal in XML 1.0 become numeric character references.
+/// Those not legal at all, such as NUL, are an error, or are dropped if strip is set.
+fn xml_escape(unescaped: &str, strip: bool) -> Result<String, Error> {
     let mut s = String::new();
     for ch in unescaped.chars() {
         match ch {
@@ -142,10 +243,19 @@ fn xml_escape(unescaped: &str) -> String {
             '\'' => s += "&apos;",
             '&' => s += "&amp;",
             '"' => s += "&quot;",
+            '\t' | '\n' | '\r' | '\u{7f}'..='\u{9f}' => s += &format!("&#{};", ch as u32),
+            _ if !is_xml_char(ch) => {
+                if !strip {
+                    return Err(anyhow!(
+                        "Character U+{:04X} cannot be represented in XML",
+                        ch as u32
+                    ));
+                }
+            }
             _ => s.push(ch),
         }
     }
-    s
+    Ok(s)
 }
 /*
 // Unit tests
@@ -237,3 +347,136 @@ fn xmlparsetest1() {
     }
 }
 */
+
+#[test]
+fn xmlgentest1() {
+    //  to_string does not flush, so make sure the output is complete.
+    let val = LLSDValue::Array(vec![LLSDValue::Integer(42), LLSDValue::String("Hello".to_string())]);
+    let s = to_string(&val, false).unwrap();
+    assert!(s.starts_with(LLSDXMLPREFIX));
+    assert!(s.ends_with("</llsd>"));
+    assert_eq!(crate::de::xml::from_str(&s).unwrap(), val);
+    //  Must match the flushing writer form exactly.
+    let mut w: Vec<u8> = Vec::new();
+    to_writer(&mut w, &val, false).unwrap();
+    assert_eq!(s.as_bytes(), w.as_slice());
+    //  Every variant, indented and not.
+    let example = LLSDValue::example();
+    assert_eq!(crate::de::xml::from_str(&to_string(&example, false).unwrap()).unwrap(), example);
+    assert_eq!(crate::de::xml::from_str(&to_string(&example, true).unwrap()).unwrap(), example);
+}
+
+#[test]
+fn xmlgenkeyordertest1() {
+    //  Caller-specified key order, with unlisted keys following in sorted order.
+    let val = LLSDValue::Map(
+        ["d", "a", "c", "b"]
+            .iter()
+            .map(|k| (k.to_string(), LLSDValue::Undefined))
+            .collect(),
+    );
+    let options = XmlWriteOptions {
+        key_order: Some(vec!["c".to_string(), "b".to_string(), "missing".to_string()]),
+        ..Default::default()
+    };
+    let s = to_string_with_options(&val, &options).unwrap();
+    let keys: Vec<&str> = s
+        .split("<key>")
+        .skip(1)
+        .map(|k| &k[..k.find("</key>").unwrap()])
+        .collect();
+    assert_eq!(keys, vec!["c", "b", "a", "d"]);
+    assert_eq!(crate::de::xml::from_str(&s).unwrap(), val);
+}
+
+#[test]
+fn xmlgenkeyordertest2() {
+    //  A key listed twice is still written once.
+    let val = LLSDValue::Map(
+        ["a", "b", "c"]
+            .iter()
+            .map(|k| (k.to_string(), LLSDValue::Undefined))
+            .collect(),
+    );
+    let options = XmlWriteOptions {
+        key_order: Some(vec!["c".to_string(), "c".to_string(), "a".to_string(), "c".to_string()]),
+        ..Default::default()
+    };
+    let s = to_string_with_options(&val, &options).unwrap();
+    let keys: Vec<&str> = s
+        .split("<key>")
+        .skip(1)
+        .map(|k| &k[..k.find("</key>").unwrap()])
+        .collect();
+    assert_eq!(keys, vec!["c", "a", "b"]);
+    assert_eq!(crate::de::xml::from_str(&s).unwrap(), val);
+}
+
+#[test]
+fn xmlgenbase64wraptest1() {
+    //  Wrapped base64 must parse back to the same binary.
+    let val = LLSDValue::Binary((0..=255).collect());
+    let options = XmlWriteOptions { base64_wrap: Some(76), ..Default::default() };
+    let s = to_string_with_options(&val, &options).unwrap();
+    let body = &s[s.find("<binary>").unwrap() + "<binary>".len()..s.find("</binary>").unwrap()];
+    let lines: Vec<&str> = body.split('\n').collect();
+    assert!(lines.len() > 1);
+    assert!(lines.iter().all(|line| line.len() <= 76));
+    assert_eq!(crate::de::xml::from_str(&s).unwrap(), val);
+    //  Default is one line.
+    let s = to_string(&val, false).unwrap();
+    assert!(!s.contains("<binary>\n") && s.lines().any(|line| line.len() > 76));
+}
+
+#[test]
+#[allow(clippy::approx_constant)] // 3.14159 is just a test value
+fn xmlgenrealformattest1() {
+    let val = LLSDValue::Real(3.14159);
+    let options = XmlWriteOptions { real_format: RealFormat::Fixed(2), ..Default::default() };
+    let s = to_string_with_options(&val, &options).unwrap();
+    assert!(s.contains("<real>3.14</real>"));
+    let options = XmlWriteOptions { real_format: RealFormat::Scientific, ..Default::default() };
+    let s = to_string_with_options(&val, &options).unwrap();
+    assert!(s.contains("<real>3.14159e0</real>"));
+    assert_eq!(crate::de::xml::from_str(&s).unwrap(), val);
+    //  Default is unchanged.
+    assert!(to_string(&val, false).unwrap().contains("<real>3.14159</real>"));
+}
+
+#[test]
+fn xmlgencontrolchartest1() {
+    //  Vertical tab and NUL are not legal XML 1.0 at all.
+    let val = LLSDValue::String("a\u{b}b\0c".to_string());
+    assert!(to_string(&val, false).is_err());
+    let options = XmlWriteOptions { strip_invalid_chars: true, ..Default::default() };
+    let s = to_string_with_options(&val, &options).unwrap();
+    assert!(s.contains("<string>abc</string>"));
+    //  Tab, newline, CR, and DEL are legal, as character references.
+    let val = LLSDValue::String("a\tb\nc\rd\u{7f}e".to_string());
+    let s = to_string(&val, false).unwrap();
+    assert!(s.contains("<string>a&#9;b&#10;c&#13;d&#127;e</string>"));
+    assert_eq!(crate::de::xml::from_str(&s).unwrap(), val);
+    //  Keys too.
+    let val = LLSDValue::Map([("k\0".to_string(), LLSDValue::Undefined)].into_iter().collect());
+    assert!(to_string(&val, false).is_err());
+}
+
+#[test]
+fn xmlgendeclarationtest1() {
+    let val = LLSDValue::Array(vec![LLSDValue::Integer(1), LLSDValue::String("a".to_string())]);
+    let options = XmlWriteOptions { single_quote_declaration: true, ..Default::default() };
+    let s = to_string_with_options(&val, &options).unwrap();
+    assert!(s.starts_with("<?xml version='1.0' encoding='UTF-8'?>\n<llsd>\n"));
+    assert_eq!(crate::de::xml::from_str(&s).unwrap(), val);
+    let options = XmlWriteOptions { single_quote_declaration: true, omit_declaration_newline: true, ..Default::default() };
+    let s = to_string_with_options(&val, &options).unwrap();
+    assert!(s.starts_with("<?xml version='1.0' encoding='UTF-8'?><llsd>\n"));
+    assert_eq!(crate::de::xml::from_str(&s).unwrap(), val);
+    let options = XmlWriteOptions { omit_declaration_newline: true, ..Default::default() };
+    let s = to_string_with_options(&val, &options).unwrap();
+    assert!(s.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?><llsd>\n"));
+    assert_eq!(crate::de::auto_from_str(&s).unwrap(), val);
+    //  Default unchanged.
+    assert!(to_string(&val, false).unwrap().starts_with(LLSDXMLPREFIX));
+}
+
@@ -0,0 +1,54 @@
+//! # util -- small utility functions shared by the parsers.
+//!
+//!  Library for serializing and de-serializing data in
+//!  Linden Lab Structured Data format.
+//!
+//!  These are public so that callers doing their own format detection
+//!  get the same whitespace semantics as the auto-detector.
+//
+//  Animats
+//  October, 2026.
+//  License: LGPL.
+//
+
+/// Trim leading ASCII whitespace from a byte slice.
+/// From an unstable Rust feature soon to become standard.
+pub fn trim_ascii_start(b: &[u8]) -> &[u8] {
+    let mut bytes = b;
+    while let [first, rest @ ..] = bytes {
+        if first.is_ascii_whitespace() {
+            bytes = rest;
+        } else {
+            break;
+        }
+    }
+    bytes
+}
+
+/// Trim trailing ASCII whitespace from a byte slice.
+pub fn trim_ascii_end(b: &[u8]) -> &[u8] {
+    let mut bytes = b;
+    while let [rest @ .., last] = bytes {
+        if last.is_ascii_whitespace() {
+            bytes = rest;
+        } else {
+            break;
+        }
+    }
+    bytes
+}
+
+/// Trim leading and trailing ASCII whitespace from a byte slice.
+pub fn trim_ascii(b: &[u8]) -> &[u8] {
+    trim_ascii_end(trim_ascii_start(b))
+}
+
+#[test]
+fn testtrimascii() {
+    assert_eq!(trim_ascii_start(b" \t\r\nabc \n"), b"abc \n");
+    assert_eq!(trim_ascii_end(b" abc \t\r\n"), b" abc");
+    assert_eq!(trim_ascii(b"\n abc def \n"), b"abc def");
+    assert_eq!(trim_ascii(b"  \n "), b"");
+    assert_eq!(trim_ascii(b""), b"");
+    assert_eq!(trim_ascii(b"abc"), b"abc");
+}